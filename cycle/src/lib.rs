@@ -0,0 +1,301 @@
+//! Cycle detection for puzzles that repeat a step until its states loop, like spinning the
+//! platform of day 14 or pressing the button of day 20.
+//!
+//! The finders walk `initial`, `step(initial)`, `step(step(initial))`... and return a [`Cycle`],
+//! which can then answer for any iteration without walking to it.
+#![allow(clippy::needless_return)]
+
+use std::{collections::HashMap, hash::Hash};
+
+/// A sequence of states that ends up looping: after `prefix` states, the next `period` ones
+/// repeat forever.
+#[derive(Debug, Clone)]
+pub struct Cycle<S> {
+    pub prefix: usize,
+    pub period: usize,
+    /// The first `prefix + period` states, starting with the initial one.
+    pub states: Vec<S>,
+}
+
+impl<S> Cycle<S> {
+    /// Position in `states` of the state after `iteration` steps.
+    pub fn index(&self, iteration: u64) -> usize {
+        let (prefix, period) = (self.prefix as u64, self.period as u64);
+        if iteration < prefix {
+            return iteration as usize;
+        }
+        return (prefix + (iteration - prefix) % period) as usize;
+    }
+
+    /// State after `iteration` steps.
+    pub fn state_at(&self, iteration: u64) -> &S {
+        return &self.states[self.index(iteration)];
+    }
+
+    /// Some `metric` of the state after `iteration` steps.
+    pub fn metric_at<T>(&self, iteration: u64, metric: impl Fn(&S) -> T) -> T {
+        return metric(self.state_at(iteration));
+    }
+
+    /// Sum of `metric` over the states from the initial one to the one after `iteration` steps.
+    pub fn sum_through(&self, iteration: u64, metric: impl Fn(&S) -> u64) -> u64 {
+        let values: Vec<u64> = self.states.iter().map(metric).collect();
+        let prefix_sum = |count: usize| values[..count].iter().sum::<u64>();
+
+        let (prefix, period) = (self.prefix as u64, self.period as u64);
+        if iteration < prefix + period {
+            return prefix_sum(iteration as usize + 1);
+        }
+
+        let looped = iteration + 1 - prefix;
+        let whole = looped / period;
+        let remainder = (looped % period) as usize;
+        let period_sum = prefix_sum(self.prefix + self.period) - prefix_sum(self.prefix);
+
+        return prefix_sum(self.prefix + remainder) + whole * period_sum;
+    }
+}
+
+/// Finds the cycle of `step` from `initial`, remembering every state in a hash map.
+pub fn find_cycle<S: Clone + Eq + Hash>(initial: S, step: impl FnMut(&S) -> S) -> Cycle<S> {
+    return find_cycle_within(initial, usize::MAX, step).ok().unwrap();
+}
+
+/// Like [`find_cycle`], but gives up after `limit` steps, returning the states walked.
+pub fn find_cycle_within<S: Clone + Eq + Hash>(
+    initial: S,
+    limit: usize,
+    mut step: impl FnMut(&S) -> S,
+) -> Result<Cycle<S>, Vec<S>> {
+    let mut seen: HashMap<S, usize> = HashMap::new();
+    let mut states = vec![initial];
+
+    loop {
+        let last = states.last().unwrap();
+        if let Some(first) = seen.get(last) {
+            let period = states.len() - 1 - first;
+            states.pop();
+            return Ok(Cycle {
+                prefix: *first,
+                period,
+                states,
+            });
+        }
+        if states.len() > limit {
+            return Err(states);
+        }
+
+        seen.insert(last.clone(), states.len() - 1);
+        let next = step(last);
+        states.push(next);
+    }
+}
+
+/// Finds the cycle of `step` from `initial` for states too big to keep around. Only a `key` of
+/// each state is remembered, which must tell states apart, along with the metric `step` returns
+/// when leaving it. The returned cycle holds those metrics in place of the states.
+pub fn find_cycle_keyed<S, K: Eq + Hash, M>(
+    initial: S,
+    step: impl FnMut(S) -> (S, M),
+    key: impl Fn(&S) -> K,
+) -> Cycle<M> {
+    return find_cycle_keyed_within(initial, usize::MAX, step, key)
+        .ok()
+        .unwrap();
+}
+
+/// Like [`find_cycle_keyed`], but gives up after `limit` steps, returning their metrics.
+pub fn find_cycle_keyed_within<S, K: Eq + Hash, M>(
+    initial: S,
+    limit: usize,
+    mut step: impl FnMut(S) -> (S, M),
+    key: impl Fn(&S) -> K,
+) -> Result<Cycle<M>, Vec<M>> {
+    let mut seen: HashMap<K, usize> = HashMap::new();
+    let mut metrics = vec![];
+    let mut state = initial;
+
+    loop {
+        let state_key = key(&state);
+        if let Some(first) = seen.get(&state_key) {
+            return Ok(Cycle {
+                prefix: *first,
+                period: metrics.len() - first,
+                states: metrics,
+            });
+        }
+        if metrics.len() >= limit {
+            return Err(metrics);
+        }
+
+        seen.insert(state_key, metrics.len());
+        let (next, metric) = step(state);
+        metrics.push(metric);
+        state = next;
+    }
+}
+
+/// Finds the cycle of `step` from `initial` with Brent's algorithm, which only compares states
+/// and keeps a couple of them while searching. `step` is called again to collect the states, so
+/// it must always give the same result for the same state.
+pub fn find_cycle_brent<S: Clone + Eq>(initial: S, step: impl FnMut(&S) -> S) -> Cycle<S> {
+    return find_cycle_brent_within(initial, usize::MAX, step)
+        .ok()
+        .unwrap();
+}
+
+/// Like [`find_cycle_brent`], but gives up if the first `limit + 1` states don't repeat,
+/// returning them.
+pub fn find_cycle_brent_within<S: Clone + Eq>(
+    initial: S,
+    limit: usize,
+    mut step: impl FnMut(&S) -> S,
+) -> Result<Cycle<S>, Vec<S>> {
+    let walk = |step: &mut dyn FnMut(&S) -> S, count: usize| {
+        let mut states = Vec::with_capacity(count.min(1 << 16));
+        states.push(initial.clone());
+        while states.len() < count {
+            let next = step(states.last().unwrap());
+            states.push(next);
+        }
+        return states;
+    };
+
+    // The tortoise waits at each power of two for the hare, which finds it once the power is
+    // past the prefix and at least the period, so the cycle ends within the first `limit + 1`
+    // states only if it's found before the power doubles them.
+    let mut power: usize = 1;
+    let mut period = 1;
+    let mut tortoise = initial.clone();
+    let mut hare = step(&initial);
+    while tortoise != hare {
+        if power / 2 > limit {
+            return Err(walk(&mut step, limit.saturating_add(1)));
+        }
+        if power == period {
+            tortoise = hare.clone();
+            power *= 2;
+            period = 0;
+        }
+        hare = step(&hare);
+        period += 1;
+    }
+
+    // With the hare a period ahead, both meet at the start of the cycle.
+    let mut tortoise = initial.clone();
+    let mut hare = initial.clone();
+    for _ in 0..period {
+        hare = step(&hare);
+    }
+    let mut prefix = 0;
+    while tortoise != hare {
+        tortoise = step(&tortoise);
+        hare = step(&hare);
+        prefix += 1;
+    }
+
+    if prefix + period > limit {
+        return Err(walk(&mut step, limit.saturating_add(1)));
+    }
+    return Ok(Cycle {
+        prefix,
+        period,
+        states: walk(&mut step, prefix + period),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Step over `0, 1, 2...` that goes back to `prefix` after `prefix + period - 1`.
+    fn rho(prefix: usize, period: usize) -> impl Fn(&usize) -> usize {
+        return move |&i| {
+            if i + 1 < prefix + period {
+                i + 1
+            } else {
+                prefix
+            }
+        };
+    }
+
+    /// The first `count` states from 0, walked one by one.
+    fn walk(step: impl Fn(&usize) -> usize, count: usize) -> Vec<usize> {
+        let mut states = vec![0];
+        while states.len() < count {
+            states.push(step(states.last().unwrap()));
+        }
+        states.truncate(count);
+        return states;
+    }
+
+    const SHAPES: [(usize, usize); 6] = [(0, 1), (0, 5), (1, 1), (3, 1), (3, 4), (7, 13)];
+
+    #[test]
+    fn finders_agree() {
+        for (prefix, period) in SHAPES {
+            let step = rho(prefix, period);
+            let expected = walk(&step, prefix + period);
+
+            let hashed = find_cycle(0, &step);
+            let brent = find_cycle_brent(0, &step);
+            let keyed = find_cycle_keyed(0, |s| (step(&s), s), |s| *s);
+            for cycle in [hashed, brent, keyed] {
+                assert_eq!((cycle.prefix, cycle.period), (prefix, period));
+                assert_eq!(cycle.states, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn limits() {
+        for (prefix, period) in SHAPES {
+            let step = rho(prefix, period);
+            let length = prefix + period;
+
+            for limit in [length - 1, length, length + 1] {
+                let hashed = find_cycle_within(0, limit, &step);
+                let brent = find_cycle_brent_within(0, limit, &step);
+                let keyed = find_cycle_keyed_within(0, limit, |s| (step(&s), s), |s| *s);
+
+                if limit < length {
+                    // The walked states, including the one after `limit` steps.
+                    assert_eq!(hashed.unwrap_err(), walk(&step, limit + 1));
+                    assert_eq!(brent.unwrap_err(), walk(&step, limit + 1));
+                    // The metric of each of the `limit` steps.
+                    assert_eq!(keyed.unwrap_err(), walk(&step, limit));
+                } else {
+                    for cycle in [hashed.unwrap(), brent.unwrap(), keyed.unwrap()] {
+                        assert_eq!((cycle.prefix, cycle.period), (prefix, period));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn iterations() {
+        for (prefix, period) in SHAPES {
+            let step = rho(prefix, period);
+            let cycle = find_cycle(0, &step);
+
+            // Well past the boundary between the prefix and the period, a few times around.
+            let count = 3 * (prefix + period) + 5;
+            let states = walk(&step, count);
+            let metric = |s: &usize| 10 + *s as u64 * 7;
+            let mut sum = 0;
+            for (iteration, state) in states.iter().enumerate() {
+                sum += metric(state);
+                let iteration = iteration as u64;
+                assert_eq!(cycle.state_at(iteration), state);
+                assert_eq!(cycle.metric_at(iteration, metric), metric(state));
+                assert_eq!(cycle.sum_through(iteration, metric), sum);
+            }
+
+            // Far iterations land where the period says.
+            let far = 1_000_000_007;
+            let expected = prefix + (far - prefix) % period;
+            assert_eq!(cycle.index(far as u64), expected);
+        }
+    }
+}
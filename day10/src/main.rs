@@ -1,3 +1,5 @@
+#![allow(clippy::needless_return)]
+
 use std::io::{self, Read};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -8,6 +10,48 @@ enum Direction {
     West,
 }
 
+const DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+impl Direction {
+    fn opposite(&self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+        }
+    }
+}
+
+/// Sides a pipe connects.
+fn connections(c: char) -> Option<[Direction; 2]> {
+    match c {
+        '|' => Some([Direction::North, Direction::South]),
+        '-' => Some([Direction::East, Direction::West]),
+        'L' => Some([Direction::North, Direction::East]),
+        'J' => Some([Direction::North, Direction::West]),
+        '7' => Some([Direction::South, Direction::West]),
+        'F' => Some([Direction::South, Direction::East]),
+        _ => None,
+    }
+}
+
+/// The pipe connecting both sides.
+fn pipe(a: Direction, b: Direction) -> char {
+    return ['|', '-', 'L', 'J', '7', 'F']
+        .into_iter()
+        .find(|c| {
+            let [c1, c2] = connections(*c).unwrap();
+            (c1, c2) == (a, b) || (c1, c2) == (b, a)
+        })
+        .unwrap();
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum CellType {
     Wall,
@@ -45,14 +89,89 @@ fn index_to_point(idx: usize, map: &Map) -> Option<(i64, i64)> {
     return Some((x as i64, y as i64));
 }
 
-fn main() {
-    println!("2023 AoC - Day 10");
-    let mut buff = String::new();
+fn neighbour(idx: usize, direction: Direction, map: &Map) -> Option<usize> {
+    let (x, y) = index_to_point(idx, map).unwrap();
+    match direction {
+        Direction::North => point_to_index(x, y - 1, map),
+        Direction::South => point_to_index(x, y + 1, map),
+        Direction::East => point_to_index(x + 1, y, map),
+        Direction::West => point_to_index(x - 1, y, map),
+    }
+}
 
-    io::stdin()
-        .read_to_string(&mut buff)
-        .expect("Could not read input!");
+/// Finds the loop through `S`, and replaces it with the pipe it has to be. Returns the cells of
+/// the loop in order, starting with `S`.
+///
+/// Every pair of neighbours connecting to `S` is tried as its shape. Exactly one of them has to
+/// lead back to `S` from the other, otherwise there is no loop or it is ambiguous.
+fn trace_loop(map: &mut Map) -> Result<Vec<usize>, String> {
+    let start = map
+        .chars
+        .iter()
+        .position(|i| *i == 'S')
+        .ok_or("Can't find S")?;
+
+    // Sides of S with a pipe connecting back to it.
+    let open: Vec<Direction> = DIRECTIONS
+        .into_iter()
+        .filter(|d| {
+            neighbour(start, *d, map)
+                .and_then(|idx| connections(map.chars[idx]))
+                .is_some_and(|sides| sides.contains(&d.opposite()))
+        })
+        .collect();
+
+    let mut loops = vec![];
+    for (i, first) in open.iter().enumerate() {
+        for last in &open[i + 1..] {
+            // Walk from S through `first`, until the path ends or gets back to S.
+            let mut path = vec![start];
+            let mut idx = start;
+            let mut direction = *first;
+            while path.len() <= map.chars.len() {
+                let Some(next) = neighbour(idx, direction, map) else {
+                    break;
+                };
+                if next == start {
+                    if direction == last.opposite() {
+                        loops.push((pipe(*first, *last), path));
+                    }
+                    break;
+                }
 
+                let Some(sides) = connections(map.chars[next]) else {
+                    break;
+                };
+                let from = direction.opposite();
+                if !sides.contains(&from) {
+                    break;
+                }
+
+                direction = if sides[0] == from { sides[1] } else { sides[0] };
+                idx = next;
+                path.push(idx);
+            }
+        }
+    }
+
+    match loops.len() {
+        0 => return Err("S isn't part of any closed loop".to_string()),
+        1 => {}
+        _ => {
+            let shapes: Vec<String> = loops.iter().map(|(shape, _)| shape.to_string()).collect();
+            return Err(format!(
+                "S closes a different loop as each of {}",
+                shapes.join(", ")
+            ));
+        }
+    }
+
+    let (shape, path) = loops.pop().unwrap();
+    map.chars[start] = shape;
+    return Ok(path);
+}
+
+fn parse_map(buff: &str) -> Map {
     let buff: Vec<&str> = buff.split("\n").filter(|line| !line.is_empty()).collect();
     let height = buff.len() as u64;
     let width = buff[0].len() as u64;
@@ -65,228 +184,275 @@ fn main() {
     }
 
     let cells: Vec<CellType> = vec![CellType::Unknown; chars.len()];
-    let mut map = Map {
+    return Map {
         width,
         height,
         chars,
         cells,
     };
+}
 
-    dbg!(map.height, map.width);
+fn main() {
+    println!("2023 AoC - Day 10");
 
-    // Part 1 ----------------------------------------------------------------
-    // Find the S in the buffer.
-    let start = map
-        .chars
-        .iter()
-        .position(|i| *i == 'S')
-        .expect("Cant find S");
+    let mut render = false;
+    let mut squeeze = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--render" => render = true,
+            "--squeeze" => squeeze = true,
+            other => panic!("Unknown argument {other}"),
+        }
+    }
+    // Following the NO_COLOR convention.
+    let colors = std::env::var_os("NO_COLOR").is_none();
 
-    map.cells[start] = CellType::Wall;
-    let start = index_to_point(start, &map).unwrap();
+    let mut buff = String::new();
+
+    io::stdin()
+        .read_to_string(&mut buff)
+        .expect("Could not read input!");
+
+    let mut map = parse_map(&buff);
 
-    // Nodes are made of the next cell index, and the direction we arrive from
-    let mut nodes: Vec<(usize, Direction)> = vec![];
+    dbg!(map.height, map.width);
 
-    // Gather all posible next positions from this point.
-    // North
-    match point_to_index(start.0, start.1 - 1, &map) {
-        Some(idx) => nodes.push((idx, Direction::South)),
-        None => { /* Do nothing */ }
+    // Part 1 ----------------------------------------------------------------
+    // The farthest cell is half way around the loop.
+    let path = trace_loop(&mut map).unwrap_or_else(|err| panic!("Invalid map: {err}"));
+    for idx in &path {
+        map.cells[*idx] = CellType::Wall;
     }
 
-    // South
-    match point_to_index(start.0, start.1 + 1, &map) {
-        Some(idx) => nodes.push((idx, Direction::North)),
-        None => { /* Do nothing */ }
+    let steps = path.len() / 2;
+    println!("PART1: {steps}");
+
+    // Part 2 ----------------------------------------------------------------
+    // Walls are marked in cells, so every other cell can be labeled. Both ways of counting the
+    // enclosed tiles have to agree.
+    let part2 = enclosed_tiles(&path, &map);
+    let inside = classify_cells(&mut map);
+    if inside != part2 {
+        println!("Ray casting finds {inside} tiles inside the loop, but it encloses {part2}!");
     }
 
-    // East
-    match point_to_index(start.0 + 1, start.1, &map) {
-        Some(idx) => nodes.push((idx, Direction::West)),
-        None => { /* Do nothing */ }
+    println!("PART2: {part2}");
+
+    if render {
+        render_map(&map, colors);
+    }
+    if squeeze {
+        render_squeezed(&map, colors);
     }
+}
 
-    // West
-    match point_to_index(start.0 - 1, start.1, &map) {
-        Some(idx) => nodes.push((idx, Direction::East)),
-        None => { /* Do nothing */ }
+/// Tiles enclosed by the loop. The shoelace formula gives the area of the polygon through the
+/// centers of the loop's cells, and Pick's theorem the lattice points inside it: A = I + B / 2 - 1,
+/// with every cell of the loop being one of the B points on its boundary.
+fn enclosed_tiles(path: &[usize], map: &Map) -> u64 {
+    let points: Vec<(i64, i64)> = path
+        .iter()
+        .map(|idx| index_to_point(*idx, map).unwrap())
+        .collect();
+
+    let mut double_area = 0;
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        let (x2, y2) = points[(i + 1) % points.len()];
+        double_area += x1 * y2 - x2 * y1;
     }
 
-    let mut steps: u64 = 1;
-    let mut converged_paths = false;
-    while !converged_paths {
-        let mut next_nodes: Vec<(usize, Direction)> = vec![];
-
-        // Walk each path until two reach the same cell.
-        while nodes.len() != 0 {
-            let (idx, dir) = nodes.pop().unwrap();
-            let (x, y) = index_to_point(idx, &map).unwrap();
-            let new_idx: usize;
-            map.cells[idx] = CellType::Wall;
-            match dir {
-                Direction::North => match map.chars[idx] {
-                    'L' => {
-                        new_idx = point_to_index(x + 1, y, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::West));
-                    }
-                    'J' => {
-                        new_idx = point_to_index(x - 1, y, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::East));
-                    }
-                    '|' => {
-                        new_idx = point_to_index(x, y + 1, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::North));
-                    }
-                    _ => {
-                        map.cells[idx] = CellType::Unknown; /* Invalid path, ends here.*/
-                    }
-                },
-                Direction::South => match map.chars[idx] {
-                    'F' => {
-                        new_idx = point_to_index(x + 1, y, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::West));
-                    }
-                    '7' => {
-                        new_idx = point_to_index(x - 1, y, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::East));
-                    }
-                    '|' => {
-                        new_idx = point_to_index(x, y - 1, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::South));
-                    }
-                    _ => {
-                        map.cells[idx] = CellType::Unknown; /* Invalid path, ends here.*/
-                    }
-                },
-                Direction::East => match map.chars[idx] {
-                    'F' => {
-                        new_idx = point_to_index(x, y + 1, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::North));
-                    }
-                    'L' => {
-                        new_idx = point_to_index(x, y - 1, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::South));
-                    }
-                    '-' => {
-                        new_idx = point_to_index(x - 1, y, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::East));
-                    }
-                    _ => {
-                        map.cells[idx] = CellType::Unknown; /* Invalid path, ends here.*/
-                    }
-                },
-                Direction::West => match map.chars[idx] {
-                    '7' => {
-                        new_idx = point_to_index(x, y + 1, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::North));
-                    }
-                    'J' => {
-                        new_idx = point_to_index(x, y - 1, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::South));
-                    }
-                    '-' => {
-                        new_idx = point_to_index(x + 1, y, &map).unwrap();
-                        next_nodes.push((new_idx, Direction::West));
-                    }
-                    _ => {
-                        map.cells[idx] = CellType::Unknown; /* Invalid path, ends here.*/
-                    }
-                },
+    let boundary = path.len() as i64;
+    return ((double_area.abs() - boundary) / 2 + 1) as u64;
+}
+
+/// Labels every cell that isn't part of the loop as `Inside` or `Outside`, returning how many
+/// are inside. A ray from a cell to the West edge crosses the loop once for every pipe it passes
+/// that opens to the North, so an odd count means the cell is enclosed.
+fn classify_cells(map: &mut Map) -> u64 {
+    let mut inside = 0;
+    for y in 0..map.height as i64 {
+        let mut crossings = 0;
+        for x in 0..map.width as i64 {
+            let idx = point_to_index(x, y, map).unwrap();
+            if map.cells[idx] == CellType::Wall {
+                let sides = connections(map.chars[idx]).unwrap();
+                if sides.contains(&Direction::North) {
+                    crossings += 1;
+                }
+            } else if crossings % 2 == 1 {
+                map.cells[idx] = CellType::Inside;
+                inside += 1;
+            } else {
+                map.cells[idx] = CellType::Outside;
             }
         }
+    }
 
-        steps += 1;
+    return inside;
+}
 
-        // If any index is repeated in next_nodes, I can end the search.
-        let indexes: Vec<usize> = next_nodes.iter().map(|s| s.0).collect();
-        for (i, index) in indexes.iter().enumerate() {
-            if indexes[i + 1..indexes.len()].contains(index) {
-                converged_paths = true;
-                map.cells[*index] = CellType::Wall;
-                break;
-            }
+// ANSI styles for the renderings.
+const LOOP_STYLE: &str = "\x1b[1;97m";
+const JUNK_STYLE: &str = "\x1b[2m";
+const INSIDE_STYLE: &str = "\x1b[32m";
+const OUTSIDE_STYLE: &str = "\x1b[34m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+/// The box-drawing character for a pipe.
+fn box_char(c: char) -> char {
+    match c {
+        '|' => '│',
+        '-' => '─',
+        'L' => '└',
+        'J' => '┘',
+        '7' => '┐',
+        'F' => '┌',
+        _ => '·',
+    }
+}
+
+fn paint(c: char, style: &str, colors: bool) -> String {
+    return match colors {
+        true => format!("{style}{c}{RESET_STYLE}"),
+        false => c.to_string(),
+    };
+}
+
+fn region_style(cell: CellType) -> &'static str {
+    return match cell {
+        CellType::Inside => INSIDE_STYLE,
+        _ => OUTSIDE_STYLE,
+    };
+}
+
+/// Prints the map with the loop drawn in box-drawing characters, and the pipes that aren't part
+/// of it faded in the color of their region. Without colors, the regions are shown as `I` and
+/// `O` instead.
+fn render_map(map: &Map, colors: bool) {
+    println!("Map {}x{}", map.width, map.height);
+    for (i, cell) in map.cells.iter().enumerate() {
+        if i != 0 && i % map.width as usize == 0 {
+            println!();
         }
 
-        nodes = next_nodes;
+        let c = map.chars[i];
+        let text = match (cell, colors) {
+            (CellType::Wall, _) => paint(box_char(c), LOOP_STYLE, colors),
+            (CellType::Unknown, _) => paint('?', JUNK_STYLE, colors),
+            (_, false) => (if *cell == CellType::Inside { 'I' } else { 'O' }).to_string(),
+            (_, true) if connections(c).is_some() => {
+                let style = format!("{JUNK_STYLE}{}", region_style(*cell));
+                paint(box_char(c), &style, colors)
+            }
+            (_, true) => paint('·', region_style(*cell), colors),
+        };
+        print!("{text}");
     }
+    println!("\n");
+}
 
-    println!("PART1: {steps}");
+/// Prints the map three times bigger, each loop pipe drawn through the middle of its 3x3 block.
+/// The gaps between pipes that only touch become visible, and the parts of them reachable from
+/// the edges, squeezing between pipes, are painted as outside.
+fn render_squeezed(map: &Map, colors: bool) {
+    let width = 3 * map.width as usize;
+    let height = 3 * map.height as usize;
+
+    // The loop drawn with its arms on the bigger grid.
+    let mut walls: Vec<Option<char>> = vec![None; width * height];
+    for (i, cell) in map.cells.iter().enumerate() {
+        if *cell != CellType::Wall {
+            continue;
+        }
 
-    // Part 2 ----------------------------------------------------------------
-    // At this point, walls should be marked in Cells. I can determine if a cell is inside or
-    // outisde of the loop by how many walls are crossed. Since we know the loop is closed, 0,0 is
-    // either a Wall or Outisde.
-
-    let mut part2 = 0;
-    let mut current_state = CellType::Outside;
-    let mut flow = Direction::North;
-
-    for i in 0..map.cells.len() {
-        // For each cell that is not wall, determine if it is outside or inside.
-        // When starting a new row, check if starting position is wall.
-        if i & map.width as usize == 0 && map.cells[0] == CellType::Wall {
-            current_state = CellType::Inside;
-            if "JL".contains(map.chars[i]) {
-                flow = Direction::North;
-            } else if "F7".contains(map.chars[i]) {
-                flow = Direction::South;
-            }
+        let (x, y) = index_to_point(i, map).unwrap();
+        let (cx, cy) = (3 * x as usize + 1, 3 * y as usize + 1);
+        walls[cy * width + cx] = Some(box_char(map.chars[i]));
+        for side in connections(map.chars[i]).unwrap() {
+            let (arm, c) = match side {
+                Direction::North => ((cy - 1) * width + cx, '│'),
+                Direction::South => ((cy + 1) * width + cx, '│'),
+                Direction::East => (cy * width + cx + 1, '─'),
+                Direction::West => (cy * width + cx - 1, '─'),
+            };
+            walls[arm] = Some(c);
         }
+    }
 
-        // Check for domain change.
-        match map.cells[i] {
-            CellType::Wall => {
-                if map.chars[i] == '|'
-                    || flow == Direction::North && "JL".contains(map.chars[i])
-                    || flow == Direction::South && "7F".contains(map.chars[i])
-                {
-                    current_state = if current_state == CellType::Outside {
-                        CellType::Inside
-                    } else {
-                        CellType::Outside
-                    };
-
-                    if "JL".contains(map.chars[i]) {
-                        flow = Direction::North;
-                    } else if "F7".contains(map.chars[i]) {
-                        flow = Direction::South;
-                    }
-                }
-            }
-            CellType::Unknown => {
-                map.cells[i] = current_state;
-                if current_state == CellType::Inside {
-                    part2 += 1;
-                }
-            }
-            _ => {}
+    // Flood the gaps from the edges.
+    let mut outside = vec![false; width * height];
+    let mut pending: Vec<usize> = (0..width * height)
+        .filter(|i| {
+            let (x, y) = (i % width, i / width);
+            x == 0 || y == 0 || x == width - 1 || y == height - 1
+        })
+        .collect();
+    while let Some(i) = pending.pop() {
+        if outside[i] || walls[i].is_some() {
+            continue;
         }
+        outside[i] = true;
 
-        if map.cells[0] == CellType::Wall {
-            current_state = CellType::Inside;
+        let (x, y) = (i % width, i / width);
+        if x > 0 {
+            pending.push(i - 1);
+        }
+        if x + 1 < width {
+            pending.push(i + 1);
+        }
+        if y > 0 {
+            pending.push(i - width);
+        }
+        if y + 1 < height {
+            pending.push(i + width);
         }
     }
 
-    println!("PART2: {part2}");
+    println!("Squeezed map {width}x{height}");
+    for y in 0..height {
+        let line: String = (0..width)
+            .map(|x| {
+                let i = y * width + x;
+                match (walls[i], outside[i], colors) {
+                    (Some(c), _, _) => paint(c, LOOP_STYLE, colors),
+                    (None, true, true) => paint('·', OUTSIDE_STYLE, colors),
+                    (None, false, true) => paint('·', INSIDE_STYLE, colors),
+                    (None, true, false) => " ".to_string(),
+                    (None, false, false) => "I".to_string(),
+                }
+            })
+            .collect();
+        println!("{line}");
+    }
+    println!();
 }
 
-fn _print_map(map: &Map) {
-    for i in 0..map.cells.len() {
-        if i != 0 && i % map.width as usize == 0 {
-            println!();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both ways of counting the tiles enclosed by the loop of a map.
+    fn count_both(input: &str) -> (u64, u64) {
+        let mut map = parse_map(input);
+        let path = trace_loop(&mut map).unwrap();
+        for idx in &path {
+            map.cells[*idx] = CellType::Wall;
         }
 
-        print!(
-            "{}",
-            match map.cells[i] {
-                CellType::Wall => 'W',
-                CellType::Inside => 'I',
-                CellType::Outside => 'O',
-                CellType::Unknown => '?',
-            }
-        );
+        return (enclosed_tiles(&path, &map), classify_cells(&mut map));
     }
 
-    println!();
+    #[test]
+    fn enclosed_tiles_agree() {
+        let samples = [
+            (include_str!("../sample.txt"), 1),
+            (include_str!("../sample3_4.txt"), 4),
+            (include_str!("../sample4_4.txt"), 4),
+            (include_str!("../sample5_8.txt"), 8),
+            (include_str!("../sample6_10.txt"), 10),
+        ];
+        for (input, expected) in samples {
+            assert_eq!(count_both(input), (expected, expected));
+        }
+    }
 }
@@ -1,224 +1,338 @@
-use std::{
-    collections::HashMap,
-    io::{stdin, Read},
-};
+#![allow(clippy::needless_return)]
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct Map {
-    width: usize,
-    height: usize,
-    chars: Vec<char>,
+use cycle::find_cycle_brent_within;
+use std::io::{stdin, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    North,
+    West,
+    South,
+    East,
 }
 
-impl Map {
-    fn _print(&self) {
-        println!("Map {}x{}", self.width, self.height);
-        for i in 0..self.chars.len() {
-            if i != 0 && i % self.width as usize == 0 {
-                println!();
-            }
-            print!("{}", self.chars[i]);
+impl Direction {
+    fn parse(s: &str) -> Option<Direction> {
+        match s.trim() {
+            "N" => Some(Direction::North),
+            "W" => Some(Direction::West),
+            "S" => Some(Direction::South),
+            "E" => Some(Direction::East),
+            _ => None,
         }
-
-        println!();
     }
+}
 
-    fn to_index(&self, x: usize, y: usize) -> Option<usize> {
-        if x >= self.width || y >= self.height {
-            return None;
+/// One bit per column of a platform row.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Bits {
+    words: Vec<u64>,
+}
+
+impl Bits {
+    fn new(len: usize) -> Bits {
+        Bits {
+            words: vec![0; len.div_ceil(64)],
         }
+    }
 
-        let index = y * self.width + x;
+    fn set(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
 
-        return Some(index);
+    fn count(&self) -> u64 {
+        return self.words.iter().map(|w| w.count_ones() as u64).sum();
     }
 
-    fn _to_point(&self, idx: usize) -> Option<(usize, usize)> {
-        if idx >= self.chars.len() {
-            return None;
-        }
+    fn is_empty(&self) -> bool {
+        return self.words.iter().all(|w| *w == 0);
+    }
 
-        let x = idx % self.width;
-        let y = idx / self.width;
+    fn and(&self, other: &Bits) -> Bits {
+        let words = self.words.iter().zip(&other.words);
+        return Bits {
+            words: words.map(|(a, b)| a & b).collect(),
+        };
+    }
 
-        return Some((x, y));
+    /// Bits that are in neither set.
+    fn nor(&self, other: &Bits) -> Bits {
+        let words = self.words.iter().zip(&other.words);
+        return Bits {
+            words: words.map(|(a, b)| !(a | b)).collect(),
+        };
     }
 
-    fn get_column(&self, i: usize) -> Vec<char> {
-        if i >= self.width {
-            return vec![];
+    fn insert(&mut self, other: &Bits) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a |= b;
         }
-
-        let col: Vec<char> = self
-            .chars
-            .iter()
-            .enumerate()
-            .filter(|&(idx, _)| idx % self.width == i)
-            .map(|(_, c)| *c)
-            .collect();
-
-        return col;
     }
 
-    fn set_column(mut self, i: usize, new_col: &Vec<char>) -> Map {
-        if i >= self.width {
-            return self;
+    fn remove(&mut self, other: &Bits) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= !b;
         }
+    }
 
-        for y in 0..self.height {
-            let idx: usize = self.to_index(i, y).unwrap();
-            self.chars[idx] = new_col[y];
+    /// Moves every bit to the next column.
+    fn shifted_up(&self) -> Bits {
+        let mut carry = 0;
+        let mut words = Vec::with_capacity(self.words.len());
+        for w in &self.words {
+            words.push(w << 1 | carry);
+            carry = w >> 63;
         }
+        return Bits { words };
+    }
 
-        return self;
+    /// Moves every bit to the previous column.
+    fn shifted_down(&self) -> Bits {
+        let mut carry = 0;
+        let mut words = vec![0; self.words.len()];
+        for (i, w) in self.words.iter().enumerate().rev() {
+            words[i] = w >> 1 | carry;
+            carry = w << 63;
+        }
+        return Bits { words };
     }
+}
 
-    fn roll_clockwise(self) -> Map {
-        let mut new_buff: Vec<char> = vec![];
+/// The platform as a bitmask per row for round and cube rocks, so a whole row of rocks moves at
+/// once. The unused bits past the last column are cube rocks, to stop rocks rolling East.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Platform {
+    width: usize,
+    height: usize,
+    round: Vec<Bits>,
+    cube: Vec<Bits>,
+}
 
-        for x in 0..self.width {
-            let mut col = self.get_column(x);
-            col.reverse();
-            new_buff.append(&mut col);
+impl Platform {
+    fn parse(lines: &[&str]) -> Platform {
+        let width = lines[0].len();
+        let mut round = vec![];
+        let mut cube = vec![];
+        for line in lines {
+            let mut round_row = Bits::new(width);
+            let mut cube_row = Bits::new(width);
+            for x in line.len()..64 * cube_row.words.len() {
+                cube_row.set(x);
+            }
+            for (x, c) in line.chars().enumerate() {
+                match c {
+                    'O' => round_row.set(x),
+                    '#' => cube_row.set(x),
+                    _ => {}
+                }
+            }
+            round.push(round_row);
+            cube.push(cube_row);
         }
 
-        return Map {
-            width: self.height,
-            height: self.width,
-            chars: new_buff,
+        return Platform {
+            width,
+            height: lines.len(),
+            round,
+            cube,
         };
     }
-}
 
-fn main() {
-    println!("2023 AoC - Day 14");
-
-    // Gather the input map.
-    let mut buff = String::new();
-    stdin()
-        .read_to_string(&mut buff)
-        .expect("Can't read stdin!");
-
-    let buff: Vec<&str> = buff.split("\n").filter(|line| !line.is_empty()).collect();
-    let height = buff.len();
-    let width = buff[0].len();
+    fn _print(&self) {
+        let get = |bits: &Bits, x: usize| bits.words[x / 64] & (1 << (x % 64)) != 0;
 
-    let mut chars: Vec<char> = vec![];
-    for line in buff {
-        for char in line.chars() {
-            chars.push(char);
+        println!("Map {}x{}", self.width, self.height);
+        for y in 0..self.height {
+            let line: String = (0..self.width)
+                .map(|x| match (get(&self.round[y], x), get(&self.cube[y], x)) {
+                    (true, _) => 'O',
+                    (_, true) => '#',
+                    _ => '.',
+                })
+                .collect();
+            println!("{line}");
         }
+        println!();
     }
 
-    let map = Map {
-        width,
-        height,
-        chars,
-    };
-
-    // Part 1 ----------------------------------------------------------------
-    let mut tilted_map = map.clone();
-    let mut part1 = 0;
+    fn free(&self, y: usize) -> Bits {
+        return self.round[y].nor(&self.cube[y]);
+    }
 
-    // map.print();
-    for col in 0..map.width {
-        let rolled_col = tilt_column(map.get_column(col));
-        tilted_map = tilted_map.set_column(col, &rolled_col);
+    /// Rolls the rocks of each row into the previous or next rows, as far as they go. Rows
+    /// closer to the edge the platform is tilted towards settle first.
+    fn tilt_rows(&mut self, towards_start: bool) {
+        let rows: Vec<usize> = match towards_start {
+            true => (1..self.height).collect(),
+            false => (0..self.height - 1).rev().collect(),
+        };
 
-        part1 += score_column(&rolled_col);
+        for y in rows {
+            let mut moving = self.round[y].clone();
+            let mut from = y;
+            loop {
+                let to = match towards_start {
+                    true if from > 0 => from - 1,
+                    false if from + 1 < self.height => from + 1,
+                    _ => break,
+                };
+
+                moving = moving.and(&self.free(to));
+                if moving.is_empty() {
+                    break;
+                }
+                self.round[from].remove(&moving);
+                self.round[to].insert(&moving);
+                from = to;
+            }
+        }
     }
 
-    println!("PART 1: {part1}");
-
-    // Part 2 .---------------------------------------------------------------
-    //
-    let mut cycle_cache: HashMap<Map, u64> = HashMap::new();
-    let mut scores: Vec<u64> = vec![];
-    let mut cycles = 0;
-    let period;
-    let offset;
-
-    // Perform cycles and store the results unitl a repeated condition is found.
-    let mut rollable_map = map.clone();
-    loop {
-        // Tilt North, West, South and East.
-        for _ in 0..4 {
-            // Tilt North, West, South and East.
-            for col in 0..rollable_map.width {
-                let tilted_column = tilt_column(rollable_map.get_column(col));
-                rollable_map = rollable_map.set_column(col, &tilted_column);
+    /// Rolls the rocks of every row one column at a time, until none of them can move.
+    fn tilt_columns(&mut self, towards_start: bool) {
+        for y in 0..self.height {
+            loop {
+                // Rocks with a free column next to them.
+                let free = self.free(y);
+                let moving = match towards_start {
+                    true => self.round[y].and(&free.shifted_up()),
+                    false => self.round[y].and(&free.shifted_down()),
+                };
+                if moving.is_empty() {
+                    break;
+                }
+
+                self.round[y].remove(&moving);
+                match towards_start {
+                    true => self.round[y].insert(&moving.shifted_down()),
+                    false => self.round[y].insert(&moving.shifted_up()),
+                }
             }
+        }
+    }
 
-            rollable_map = rollable_map.roll_clockwise();
+    fn tilt(&mut self, direction: Direction) {
+        match direction {
+            Direction::North => self.tilt_rows(true),
+            Direction::South => self.tilt_rows(false),
+            Direction::West => self.tilt_columns(true),
+            Direction::East => self.tilt_columns(false),
         }
+    }
 
-        cycles += 1;
-        let mut cycle_score = 0;
-        for col in 0..rollable_map.width {
-            cycle_score += score_column(&rollable_map.get_column(col));
+    /// Load on the support beams of the `edge` side: each round rock adds its distance to the
+    /// opposite edge, counting its own row or column.
+    fn load(&self, edge: Direction) -> u64 {
+        let mut load = 0;
+        for (y, row) in self.round.iter().enumerate() {
+            match edge {
+                Direction::North => load += row.count() * (self.height - y) as u64,
+                Direction::South => load += row.count() * (y + 1) as u64,
+                Direction::West | Direction::East => {
+                    for (i, word) in row.words.iter().enumerate() {
+                        let mut word = *word;
+                        while word != 0 {
+                            let x = 64 * i + word.trailing_zeros() as usize;
+                            load += match edge {
+                                Direction::West => (self.width - x) as u64,
+                                _ => (x + 1) as u64,
+                            };
+                            word &= word - 1;
+                        }
+                    }
+                }
+            }
         }
 
-        // println!("Cycle {cycles}: {cycle_score}");
-        // rollable_map._print();
-        // println!("");
+        return load;
+    }
+}
 
-        // Check if this condition was already seen, or store its load if new.
-        let key_map = rollable_map.clone();
-        match cycle_cache.get(&key_map) {
-            Some(val) => {
-                period = cycles - val;
-                offset = val;
-                println!(
-                    "Detected a Cycle! This board is the same as {}, so it repeats every {} rotations!",
-                    val,
-                    period
-                );
+fn main() {
+    println!("2023 AoC - Day 14");
 
-                break;
+    // What a spin does, how many of them, and which support beams to measure.
+    let mut tilts = vec![
+        Direction::North,
+        Direction::West,
+        Direction::South,
+        Direction::East,
+    ];
+    let mut spins: u64 = 1000000000;
+    let mut edge = Direction::North;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tilts" => {
+                let list = args.next().expect("Missing the tilts, like N,W,S,E");
+                tilts = list
+                    .split(',')
+                    .map(|t| Direction::parse(t).expect("Tilts are one of N, W, S or E"))
+                    .collect();
             }
-            None => {
-                cycle_cache.insert(key_map, cycles);
-                scores.push(cycle_score);
+            "--spins" => {
+                let count = args.next().expect("Missing the amount of spins");
+                spins = count.parse().expect("Invalid amount of spins");
             }
+            "--edge" => {
+                let side = args.next().expect("Missing the edge to measure");
+                edge = Direction::parse(&side).expect("Edges are one of N, W, S or E");
+            }
+            other => panic!("Unknown argument {other}"),
         }
     }
 
-    println!("Period: {period} | Offset: {offset}");
+    // Gather the input map.
+    let mut buff = String::new();
+    stdin()
+        .read_to_string(&mut buff)
+        .expect("Can't read stdin!");
 
-    let idx = (1000000000 - offset) % period;
-    let idx = (idx + offset) as usize;
-    println!("PART 2: {idx} {}", scores[idx - 1]);
-}
+    let buff: Vec<&str> = buff.split("\n").filter(|line| !line.is_empty()).collect();
+    let platform = Platform::parse(&buff);
 
-fn score_column(column: &Vec<char>) -> u64 {
-    let mut sum = 0;
+    // Part 1 ----------------------------------------------------------------
+    // Tilted towards the measured edge.
+    let mut tilted = platform.clone();
+    tilted.tilt(edge);
+    let part1 = tilted.load(edge);
 
-    for y in 0..column.len() {
-        if column[y] == 'O' {
-            sum += column.len() - y;
-        }
-    }
+    println!("PART 1: {part1}");
 
-    return sum as u64;
-}
+    // Part 2 .---------------------------------------------------------------
+    //
+    let spin = |platform: &Platform| {
+        let mut platform = platform.clone();
+        for direction in &tilts {
+            platform.tilt(*direction);
+        }
 
-fn tilt_column(column: Vec<char>) -> Vec<char> {
-    let mut empty_spot = 0;
-    let mut new_col = column.clone();
+        return platform;
+    };
 
-    for i in 0..column.len() {
-        match column[i] {
-            '#' => {
-                empty_spot = i + 1;
-            }
-            'O' => {
-                new_col[i] = '.';
-                new_col[empty_spot] = 'O';
-                empty_spot += 1;
-            }
-            _ => {}
+    // Spinning ends up repeating the same boards. Few spins are walked instead, if the board
+    // doesn't repeat before them.
+    let limit = usize::try_from(spins).unwrap_or(usize::MAX);
+    let (index, part2) = match find_cycle_brent_within(platform, limit, spin) {
+        Ok(cycle) => {
+            println!(
+                "Detected a Cycle! The board after {} spins repeats every {} spins!",
+                cycle.prefix, cycle.period
+            );
+            println!("Period: {} | Offset: {}", cycle.period, cycle.prefix);
+
+            let loads: Vec<String> = (cycle.prefix..cycle.prefix + cycle.period)
+                .map(|i| cycle.states[i].load(edge).to_string())
+                .collect();
+            println!("Loads over the cycle: {}", loads.join(", "));
+
+            (cycle.index(spins), cycle.metric_at(spins, |p| p.load(edge)))
         }
-    }
+        Err(states) => {
+            println!("No cycle within {spins} spins, spun the board directly.");
+            (limit, states[limit].load(edge))
+        }
+    };
 
-    return new_col;
+    println!("PART 2: {index} {part2}");
 }
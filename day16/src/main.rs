@@ -1,4 +1,9 @@
-use std::io::{stdin, Read};
+#![allow(clippy::needless_return)]
+
+use std::{
+    collections::HashMap,
+    io::{stdin, Read},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Direction {
@@ -8,12 +13,300 @@ enum Direction {
     West,
 }
 
+const DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+/// The puzzle's optical elements, in the format of optics files. Each line is a tile followed by
+/// the directions a beam leaves it in when entering it moving North, South, East and West, or `-`
+/// if the beam is absorbed.
+const DEFAULT_OPTICS: &str = "
+// Tile  North  South  East  West
+.        N      S      E     W
+/        E      W      N     S
+\\        W      E      S     N
+-        EW     EW     E     W
+|        N      S      NS    NS
+";
+
+/// Directions a beam leaves each kind of tile in, for each direction it can enter it in.
+#[derive(Debug, Clone, Default)]
+struct Optics {
+    elements: HashMap<char, [Vec<Direction>; 4]>,
+}
+
+impl Optics {
+    fn parse(buff: &str) -> Result<Optics, String> {
+        let mut optics = Optics::default();
+        for (i, line) in buff.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let mut tile = fields[0].chars();
+            let (Some(c), None, 5) = (tile.next(), tile.next(), fields.len()) else {
+                return Err(format!(
+                    "line {}: expected a tile and four sets of directions",
+                    i + 1
+                ));
+            };
+
+            let mut outputs: [Vec<Direction>; 4] = Default::default();
+            for (output, field) in outputs.iter_mut().zip(&fields[1..]) {
+                if *field == "-" {
+                    continue;
+                }
+                for d in field.chars() {
+                    match parse_direction(d) {
+                        Some(direction) => output.push(direction),
+                        None => return Err(format!("line {}: unknown direction '{d}'", i + 1)),
+                    }
+                }
+            }
+            optics.elements.insert(c, outputs);
+        }
+
+        return Ok(optics);
+    }
+
+    /// Adds the elements of `other`, replacing the ones using the same tiles.
+    fn extend(&mut self, other: Optics) {
+        self.elements.extend(other.elements);
+    }
+
+    /// Directions a beam leaves tile `c` in, when entering it moving in `direction`.
+    fn deflect(&self, c: char, direction: Direction) -> &[Direction] {
+        return &self.elements[&c][direction as usize];
+    }
+
+    /// Whether beams cross tile `c` without changing direction.
+    fn is_transparent(&self, c: char) -> bool {
+        return DIRECTIONS
+            .iter()
+            .all(|d| self.deflect(c, *d) == [*d].as_slice());
+    }
+}
+
+/// Set of tiles, one bit each.
+#[derive(Debug, Clone)]
+struct TileSet {
+    words: Vec<u64>,
+}
+
+impl TileSet {
+    fn new(tiles: usize) -> TileSet {
+        TileSet {
+            words: vec![0; tiles.div_ceil(64)],
+        }
+    }
+
+    fn insert(&mut self, idx: usize) {
+        self.words[idx / 64] |= 1 << (idx % 64);
+    }
+
+    fn union_with(&mut self, other: &TileSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    fn len(&self) -> u64 {
+        return self.words.iter().map(|w| w.count_ones() as u64).sum();
+    }
+}
+
+/// Straight run of a beam: the tiles it crosses until it hits an optical element, the graph node
+/// for that element if it does, or `None` if it leaves the map.
+#[derive(Debug)]
+struct Segment {
+    tiles: Vec<usize>,
+    end: Option<usize>,
+}
+
+/// The contraption compiled into a graph. There is a node per optical element and direction a
+/// beam can enter it in, linked by the segments the beams leaving it follow. Beams can loop, so
+/// nodes are grouped in strongly connected components, which all energize the same tiles.
+#[derive(Debug)]
+struct BeamGraph {
+    // Graph node of each tile's first direction, if the tile is an optical element.
+    nodes: Vec<Option<usize>>,
+    component: Vec<usize>,
+    // Tiles energized by a beam entering any node of each component.
+    energized: Vec<TileSet>,
+}
+
+impl BeamGraph {
+    fn new(map: &Map) -> BeamGraph {
+        let mut nodes = vec![None; map.chars.len()];
+        let mut elements = vec![];
+        for (idx, c) in map.chars.iter().enumerate() {
+            if !map.optics.is_transparent(*c) {
+                nodes[idx] = Some(4 * elements.len());
+                elements.push(idx);
+            }
+        }
+        let node = |idx: usize, direction: Direction| nodes[idx].map(|n| n + direction as usize);
+
+        let segment = |mut idx: usize, direction: Direction| {
+            let mut tiles = vec![idx];
+            while nodes[idx].is_none() {
+                match map.step(idx, direction) {
+                    Some(next) => idx = next,
+                    None => return Segment { tiles, end: None },
+                }
+                tiles.push(idx);
+            }
+            return Segment {
+                tiles,
+                end: node(idx, direction),
+            };
+        };
+
+        // Segments leaving each node.
+        let mut edges: Vec<Vec<Segment>> = Vec::with_capacity(4 * elements.len());
+        for idx in &elements {
+            for direction in DIRECTIONS {
+                edges.push(
+                    map.optics
+                        .deflect(map.chars[*idx], direction)
+                        .iter()
+                        .filter_map(|out| map.step(*idx, *out).map(|next| segment(next, *out)))
+                        .collect(),
+                );
+            }
+        }
+
+        // Tarjan's algorithm emits every component after the ones reachable from it, so their
+        // tiles can be merged in as they are found.
+        let successors: Vec<Vec<usize>> = edges
+            .iter()
+            .map(|edges| edges.iter().filter_map(|s| s.end).collect())
+            .collect();
+        let components = strongly_connected_components(&successors);
+
+        let mut component = vec![0; edges.len()];
+        let mut energized = vec![];
+        for (c, members) in components.iter().enumerate() {
+            let mut tiles = TileSet::new(map.chars.len());
+            for n in members {
+                component[*n] = c;
+            }
+            for n in members {
+                tiles.insert(elements[n / 4]);
+                for s in &edges[*n] {
+                    for idx in &s.tiles {
+                        tiles.insert(*idx);
+                    }
+                    if let Some(end) = s.end {
+                        if component[end] != c {
+                            tiles.union_with(&energized[component[end]]);
+                        }
+                    }
+                }
+            }
+            energized.push(tiles);
+        }
+
+        return BeamGraph {
+            nodes,
+            component,
+            energized,
+        };
+    }
+
+    /// Tiles energized by a beam entering tile `idx` moving in `direction`.
+    fn energized(&self, map: &Map, mut idx: usize, direction: Direction) -> TileSet {
+        let mut tiles = TileSet::new(map.chars.len());
+        loop {
+            tiles.insert(idx);
+            if let Some(node) = self.nodes[idx] {
+                let node = node + direction as usize;
+                tiles.union_with(&self.energized[self.component[node]]);
+                return tiles;
+            }
+            match map.step(idx, direction) {
+                Some(next) => idx = next,
+                None => return tiles,
+            }
+        }
+    }
+}
+
+/// Strongly connected components of a graph, each one listed after all the ones reachable from
+/// it. Iterative Tarjan's algorithm, so deep graphs don't overflow the stack.
+fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let len = successors.len();
+    let mut index = vec![usize::MAX; len];
+    let mut low = vec![0; len];
+    let mut on_stack = vec![false; len];
+    let mut stack = vec![];
+    let mut components = vec![];
+    let mut next_index = 0;
+
+    for root in 0..len {
+        if index[root] != usize::MAX {
+            continue;
+        }
+
+        // Nodes being visited, and how many of their successors were already looked at.
+        let mut visiting = vec![(root, 0)];
+        index[root] = next_index;
+        low[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some((node, child)) = visiting.last_mut() {
+            let node = *node;
+            if let Some(&next) = successors[node].get(*child) {
+                *child += 1;
+                if index[next] == usize::MAX {
+                    index[next] = next_index;
+                    low[next] = next_index;
+                    next_index += 1;
+                    stack.push(next);
+                    on_stack[next] = true;
+                    visiting.push((next, 0));
+                } else if on_stack[next] {
+                    low[node] = low[node].min(index[next]);
+                }
+                continue;
+            }
+
+            visiting.pop();
+            if let Some((parent, _)) = visiting.last() {
+                low[*parent] = low[*parent].min(low[node]);
+            }
+            if low[node] == index[node] {
+                let mut members = vec![];
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member] = false;
+                    members.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(members);
+            }
+        }
+    }
+
+    return components;
+}
+
 #[derive(Debug)]
 struct Map {
     width: usize,
     height: usize,
     chars: Vec<char>,
     visited: Vec<Vec<Direction>>,
+    optics: Optics,
 }
 
 impl Map {
@@ -38,62 +331,195 @@ impl Map {
         return Some((x as i64, y as i64));
     }
 
-    fn next(&self, idx: usize, direction: Direction) -> Option<usize> {
+    /// Neighbour of tile `idx` in `direction`, if it is inside the map.
+    fn step(&self, idx: usize, direction: Direction) -> Option<usize> {
         let (x, y) = self.to_point(idx).unwrap();
-        let next_point: (i64, i64);
         match direction {
-            Direction::North => next_point = (x, y - 1),
-            Direction::South => next_point = (x, y + 1),
-            Direction::East => next_point = (x + 1, y),
-            Direction::West => next_point = (x - 1, y),
-        };
-
-        // Check if this cell was already visited in this direction.
-        match self.to_index(next_point.0, next_point.1) {
-            Some(idx) => {
-                if !self.visited[idx].contains(&direction) {
-                    return Some(idx);
-                }
-            }
-            None => {}
+            Direction::North => self.to_index(x, y - 1),
+            Direction::South => self.to_index(x, y + 1),
+            Direction::East => self.to_index(x + 1, y),
+            Direction::West => self.to_index(x - 1, y),
         }
+    }
 
-        return None;
+    fn next(&self, idx: usize, direction: Direction) -> Option<usize> {
+        // Check if this cell was already visited in this direction.
+        return self
+            .step(idx, direction)
+            .filter(|idx| !self.visited[*idx].contains(&direction));
     }
 
-    fn _print(&self) {
+    /// Prints the beams of `stats` over the map: empty tiles show the direction they were crossed
+    /// in, or how many times if more than once. Then, with several sources, which one energized
+    /// each tile, or `*` if more than one did.
+    fn print(&self, stats: &BeamStats) {
         println!("Map {}x{}", self.width, self.height);
-        for i in 0..self.chars.len() {
-            if i != 0 && i % self.width as usize == 0 {
+        for (i, crossings) in stats.crossings.iter().enumerate() {
+            if i != 0 && i % self.width == 0 {
                 println!();
             }
 
             let mut c: char = self.chars[i];
             if c == '.' {
-                match self.visited[i].len() {
-                    0 => {}
-                    1 => match self.visited[i][0] {
-                        Direction::North => c = '^',
-                        Direction::South => c = 'v',
-                        Direction::East => c = '>',
-                        Direction::West => c = '<',
-                    },
-                    2 => c = '2',
-                    3 => c = '3',
-                    4 => c = '4',
-                    _ => c = '?',
+                match crossings[..] {
+                    [] => {}
+                    [(_, direction)] => c = direction_char(direction),
+                    _ => c = char::from_digit(crossings.len() as u32, 10).unwrap_or('?'),
                 }
             }
 
             print!("{}", c);
         }
         println!("\n");
+
+        if stats.sources < 2 {
+            return;
+        }
+
+        println!("Sources");
+        for i in 0..stats.crossings.len() {
+            if i != 0 && i % self.width == 0 {
+                println!();
+            }
+
+            let c = match stats.sources_of(i)[..] {
+                [] => '.',
+                [source] => char::from_digit(source as u32, 36).unwrap_or('?'),
+                _ => '*',
+            };
+            print!("{}", c);
+        }
+        println!("\n");
+    }
+}
+
+fn direction_char(direction: Direction) -> char {
+    match direction {
+        Direction::North => '^',
+        Direction::South => 'v',
+        Direction::East => '>',
+        Direction::West => '<',
+    }
+}
+
+fn parse_direction(c: char) -> Option<Direction> {
+    match c {
+        'N' => Some(Direction::North),
+        'S' => Some(Direction::South),
+        'E' => Some(Direction::East),
+        'W' => Some(Direction::West),
+        _ => None,
+    }
+}
+
+/// Where the beams of several sources went.
+#[derive(Debug)]
+struct BeamStats {
+    sources: usize,
+    // For each tile, the sources whose beams crossed it and the direction they did, once each.
+    crossings: Vec<Vec<(usize, Direction)>>,
+}
+
+impl BeamStats {
+    /// Tiles crossed by any beam.
+    fn energized(&self) -> u64 {
+        return self.crossings.iter().filter(|c| !c.is_empty()).count() as u64;
+    }
+
+    /// Sources whose beams crossed tile `idx`.
+    fn sources_of(&self, idx: usize) -> Vec<usize> {
+        let mut sources: Vec<usize> = self.crossings[idx].iter().map(|(s, _)| *s).collect();
+        sources.dedup();
+        return sources;
+    }
+
+    /// Tiles energized by each source.
+    fn energized_by(&self, source: usize) -> u64 {
+        return (0..self.crossings.len())
+            .filter(|idx| self.sources_of(*idx).contains(&source))
+            .count() as u64;
+    }
+
+    /// Tiles energized by both sources `a` and `b`.
+    fn overlap(&self, a: usize, b: usize) -> u64 {
+        return (0..self.crossings.len())
+            .filter(|idx| {
+                let sources = self.sources_of(*idx);
+                sources.contains(&a) && sources.contains(&b)
+            })
+            .count() as u64;
+    }
+
+    /// Prints how many tiles each source energized, and how much they overlap.
+    fn report(&self) {
+        for source in 0..self.sources {
+            println!(
+                "Source {source}: {} tiles energized",
+                self.energized_by(source)
+            );
+        }
+
+        let shared = (0..self.crossings.len())
+            .filter(|idx| self.sources_of(*idx).len() > 1)
+            .count();
+        println!(
+            "{} tiles energized, {shared} of them by more than one source",
+            self.energized()
+        );
+        for a in 0..self.sources {
+            for b in a + 1..self.sources {
+                println!(
+                    "  sources {a} and {b} overlap on {} tiles",
+                    self.overlap(a, b)
+                );
+            }
+        }
+
+        let crossed: Vec<usize> = self.crossings.iter().map(|c| c.len()).collect();
+        if let Some(most) = crossed.iter().max() {
+            println!("Most crossed tiles are crossed {most} times");
+        }
+        println!();
     }
 }
 
 fn main() {
     println!("2023 AoC - Day 16");
 
+    // Optics files add elements to the puzzle's ones, or change them.
+    let mut optics = Optics::parse(DEFAULT_OPTICS).unwrap();
+    let mut sources = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--optics" => {
+                let path = args.next().expect("Missing the optics file");
+                let buff = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|err| panic!("Could not read {path}: {err}"));
+                let extra = Optics::parse(&buff)
+                    .unwrap_or_else(|err| panic!("Invalid optics file {path}, {err}"));
+                optics.extend(extra);
+            }
+            "--source" => {
+                let source = args.next().expect("Missing a beam source, like 3,4,E");
+                let fields: Vec<&str> = source.split(',').map(|f| f.trim()).collect();
+                let [x, y, direction] = fields[..] else {
+                    panic!("Beam sources are given as x,y,direction");
+                };
+                let x: i64 = x.parse().expect("Invalid x coordinate");
+                let y: i64 = y.parse().expect("Invalid y coordinate");
+                let mut direction = direction.chars();
+                let (Some(direction), None) =
+                    (direction.next().and_then(parse_direction), direction.next())
+                else {
+                    panic!("Beam directions are one of N, S, E or W");
+                };
+                sources.push((x, y, direction));
+            }
+            other => panic!("Unknown argument {other}"),
+        }
+    }
+
     let mut buff = String::new();
     stdin()
         .read_to_string(&mut buff)
@@ -101,13 +527,25 @@ fn main() {
 
     let buff: Vec<&str> = buff.split("\n").filter(|line| !line.is_empty()).collect();
     let height = buff.len();
-    let width = buff[0].len();
+    // Tiles from optics files may take more than a byte.
+    let width = buff[0].chars().count();
 
     let mut chars: Vec<char> = vec![];
-    for line in buff {
-        for char in line.chars() {
-            chars.push(char);
+    for (y, line) in buff.iter().enumerate() {
+        let count = line.chars().count();
+        if count != width {
+            panic!("Row {y} has {count} tiles, expected {width}");
         }
+        chars.extend(line.chars());
+    }
+
+    if let Some(idx) = chars.iter().position(|c| !optics.elements.contains_key(c)) {
+        panic!(
+            "Unknown tile '{}' at {},{}, it needs an optics file",
+            chars[idx],
+            idx % width,
+            idx / width
+        );
     }
 
     let visited: Vec<Vec<Direction>> = vec![vec![]; chars.len()];
@@ -116,194 +554,97 @@ fn main() {
         height,
         chars,
         visited,
+        optics,
     };
 
     // Part 1 ---------------------------------------------------------------
     //
 
-    // map._print();
-    let part1 = propagate_light(&mut map, (0, Direction::East));
-    // map._print();
+    let part1 = propagate_light(&mut map, &[(0, Direction::East)]).energized();
 
     println!("PART 1: {part1}");
 
-    // Part 1 ---------------------------------------------------------------
+    // Part 2 ---------------------------------------------------------------
     //
 
-    // Bruteforce?
-    let mut part2 = 0;
-
-    // Starting at North:
-    for i in 0..map.width {
-        // reset visited.
-        map.visited = vec![vec![]; map.chars.len()];
-        let temp = propagate_light(&mut map, (i, Direction::South));
-
-        part2 = std::cmp::max(part2, temp);
+    // Every tile on the edges, entering the map.
+    let mut entries = vec![];
+    for x in 0..map.width {
+        entries.push((map.to_index(x as i64, 0).unwrap(), Direction::South));
+        let bottom = map.to_index(x as i64, (map.height - 1) as i64).unwrap();
+        entries.push((bottom, Direction::North));
     }
-
-    // Starting at East:
-    for i in 0..map.height {
-        // reset visited.
-        map.visited = vec![vec![]; map.chars.len()];
-        let idx = map.to_index((map.width - 1) as i64, i as i64).unwrap();
-        let temp = propagate_light(&mut map, (idx, Direction::West));
-
-        part2 = std::cmp::max(part2, temp);
+    for y in 0..map.height {
+        entries.push((map.to_index(0, y as i64).unwrap(), Direction::East));
+        let right = map.to_index((map.width - 1) as i64, y as i64).unwrap();
+        entries.push((right, Direction::West));
     }
 
-    // Starting at South:
-    for i in 0..map.width {
-        // reset visited.
-        map.visited = vec![vec![]; map.chars.len()];
-        let idx = map.to_index(i as i64, (map.height - 1) as i64).unwrap();
-        let temp = propagate_light(&mut map, (idx, Direction::North));
+    let graph = BeamGraph::new(&map);
+    let (part2, (idx, direction)) = entries
+        .into_iter()
+        .map(|(idx, dir)| (graph.energized(&map, idx, dir).len(), (idx, dir)))
+        .max_by_key(|(energized, _)| *energized)
+        .unwrap();
+    let (x, y) = map.to_point(idx).unwrap();
 
-        part2 = std::cmp::max(part2, temp);
-    }
+    println!("PART 2: {part2} (entering at {x},{y} moving {direction:?})");
 
-    // Starting at West:
-    for i in 0..map.height {
-        // reset visited.
-        map.visited = vec![vec![]; map.chars.len()];
-        let idx = map.to_index(0, i as i64).unwrap();
-        let temp = propagate_light(&mut map, (idx, Direction::East));
+    // Custom sources ---------------------------------------------------------
+    //
 
-        part2 = std::cmp::max(part2, temp);
+    if !sources.is_empty() {
+        let sources: Vec<(usize, Direction)> = sources
+            .into_iter()
+            .map(|(x, y, direction)| {
+                let idx = map
+                    .to_index(x, y)
+                    .unwrap_or_else(|| panic!("Source {x},{y} is outside the map"));
+                (idx, direction)
+            })
+            .collect();
+
+        println!();
+        let stats = propagate_light(&mut map, &sources);
+        stats.report();
+        map.print(&stats);
     }
-
-    println!("PART 2: {part2}")
 }
 
-fn propagate_light(map: &mut Map, start: (usize, Direction)) -> u64 {
-    let mut nodes: Vec<(usize, Direction)> = vec![];
-
-    // Add the start point, and start propagating.
-    nodes.push(start);
-
-    while nodes.len() != 0 {
-        let mut next_nodes: Vec<(usize, Direction)> = vec![];
-
-        for (idx, dir) in nodes {
-            // Mark as visited, and add new points to the nodes lists.
-            map.visited[idx].push(dir);
-            match map.chars[idx] {
-                '.' => match map.next(idx, dir) {
-                    Some(new_idx) => next_nodes.push((new_idx, dir)),
-                    None => {}
-                },
-                '/' => match dir {
-                    Direction::North => match map.next(idx, Direction::East) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::East)),
-                        None => {}
-                    },
-                    Direction::South => match map.next(idx, Direction::West) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::West)),
-                        None => {}
-                    },
-                    Direction::East => match map.next(idx, Direction::North) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::North)),
-                        None => {}
-                    },
-                    Direction::West => match map.next(idx, Direction::South) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::South)),
-                        None => {}
-                    },
-                },
-                '\\' => match dir {
-                    Direction::North => match map.next(idx, Direction::West) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::West)),
-                        None => {}
-                    },
-                    Direction::South => match map.next(idx, Direction::East) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::East)),
-                        None => {}
-                    },
-                    Direction::East => match map.next(idx, Direction::South) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::South)),
-                        None => {}
-                    },
-                    Direction::West => match map.next(idx, Direction::North) {
-                        Some(new_idx) => next_nodes.push((new_idx, Direction::North)),
-                        None => {}
-                    },
-                },
-                '-' => match dir {
-                    Direction::North => {
-                        match map.next(idx, Direction::East) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::East)),
-                            None => {}
-                        }
-
-                        match map.next(idx, Direction::West) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::West)),
-                            None => {}
-                        }
-                    }
-                    Direction::South => {
-                        match map.next(idx, Direction::East) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::East)),
-                            None => {}
-                        }
+/// Follows the beams of every source separately, so each tile knows which ones energized it.
+fn propagate_light(map: &mut Map, sources: &[(usize, Direction)]) -> BeamStats {
+    let mut stats = BeamStats {
+        sources: sources.len(),
+        crossings: vec![vec![]; map.chars.len()],
+    };
 
-                        match map.next(idx, Direction::West) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::West)),
-                            None => {}
-                        }
-                    }
+    for (source, start) in sources.iter().enumerate() {
+        // Add the start point, and start propagating.
+        map.visited = vec![vec![]; map.chars.len()];
+        let mut nodes: Vec<(usize, Direction)> = vec![*start];
 
-                    Direction::East => match map.next(idx, dir) {
-                        Some(new_idx) => next_nodes.push((new_idx, dir)),
-                        None => {}
-                    },
-                    Direction::West => match map.next(idx, dir) {
-                        Some(new_idx) => next_nodes.push((new_idx, dir)),
-                        None => {}
-                    },
-                },
-                '|' => match dir {
-                    Direction::East => {
-                        match map.next(idx, Direction::North) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::North)),
-                            None => {}
-                        }
+        while !nodes.is_empty() {
+            let mut next_nodes: Vec<(usize, Direction)> = vec![];
 
-                        match map.next(idx, Direction::South) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::South)),
-                            None => {}
-                        }
-                    }
-                    Direction::West => {
-                        match map.next(idx, Direction::North) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::North)),
-                            None => {}
-                        }
+            for (idx, dir) in nodes {
+                // Two beams can reach the same tile at once.
+                if map.visited[idx].contains(&dir) {
+                    continue;
+                }
 
-                        match map.next(idx, Direction::South) {
-                            Some(new_idx) => next_nodes.push((new_idx, Direction::South)),
-                            None => {}
-                        }
+                // Mark as visited, and add new points to the nodes lists.
+                map.visited[idx].push(dir);
+                stats.crossings[idx].push((source, dir));
+                for out in map.optics.deflect(map.chars[idx], dir) {
+                    if let Some(new_idx) = map.next(idx, *out) {
+                        next_nodes.push((new_idx, *out));
                     }
-
-                    Direction::North => match map.next(idx, dir) {
-                        Some(new_idx) => next_nodes.push((new_idx, dir)),
-                        None => {}
-                    },
-                    Direction::South => match map.next(idx, dir) {
-                        Some(new_idx) => next_nodes.push((new_idx, dir)),
-                        None => {}
-                    },
-                },
-                _ => {}
+                }
             }
-        }
 
-        nodes = next_nodes;
+            nodes = next_nodes;
+        }
     }
 
-    return map
-        .visited
-        .iter()
-        .map(|v| if v.len() > 0 { 1 } else { 0 })
-        .sum();
+    return stats;
 }
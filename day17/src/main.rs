@@ -1,7 +1,10 @@
+#![allow(clippy::needless_return)]
+
 use std::{
-    cmp::Ordering,
+    cmp::{Ordering, Reverse},
     collections::{BinaryHeap, HashSet},
     io::{stdin, Read},
+    time::Instant,
 };
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
@@ -13,34 +16,163 @@ enum Direction {
     West,
 }
 
-#[derive(Debug, Eq, PartialEq)]
-struct Node {
+/// A state of the search: block, traveling direction and blocks moved in that direction.
+type State = (usize, Direction, u64);
+
+/// One block of a route, entered moving in `direction`, with the heat lost up to it included.
+#[derive(Debug, Clone, Copy)]
+struct PathStep {
     idx: usize,
     direction: Direction,
     heatloss: u64,
-    consecutive_cells: u64,
 }
 
-// Implementation of ordering by least heatloss, for the priority queue.
-impl Ord for Node {
-    fn cmp(&self, rhs: &Node) -> Ordering {
-        return rhs.heatloss.cmp(&self.heatloss);
+#[derive(Debug)]
+struct Route {
+    heatloss: u64,
+    // From the start block (with no direction) to the goal.
+    steps: Vec<PathStep>,
+}
+
+/// Dense numbering of the states of a map. Each block has one state without a direction, where
+/// the crucible starts, and one per direction and amount of blocks moved in it.
+#[derive(Debug, Clone, Copy)]
+struct StateSpace {
+    max_straight: usize,
+    per_block: usize,
+}
+
+impl StateSpace {
+    fn new(rules: &CrucibleRules) -> StateSpace {
+        let max_straight = rules.max_straight as usize;
+        StateSpace {
+            max_straight,
+            per_block: 1 + 4 * max_straight,
+        }
+    }
+
+    fn index(&self, (idx, direction, run): State) -> usize {
+        let offset = match direction {
+            Direction::None => 0,
+            Direction::North => 1,
+            Direction::South => 1 + self.max_straight,
+            Direction::East => 1 + 2 * self.max_straight,
+            Direction::West => 1 + 3 * self.max_straight,
+        };
+
+        return idx * self.per_block + offset + (run as usize).saturating_sub(1);
+    }
+
+    fn state(&self, index: usize) -> State {
+        let idx = index / self.per_block;
+        let offset = index % self.per_block;
+        if offset == 0 {
+            return (idx, Direction::None, 0);
+        }
+
+        let direction = match (offset - 1) / self.max_straight {
+            0 => Direction::North,
+            1 => Direction::South,
+            2 => Direction::East,
+            _ => Direction::West,
+        };
+        let run = (offset - 1) % self.max_straight + 1;
+
+        return (idx, direction, run as u64);
     }
 }
 
-impl PartialOrd for Node {
-    fn partial_cmp(&self, rhs: &Node) -> Option<Ordering> {
-        return Some(self.cmp(rhs));
+/// Priority queue of state indexes, least priority first.
+trait Frontier {
+    fn push(&mut self, priority: u64, state: usize);
+    fn pop(&mut self) -> Option<(u64, usize)>;
+}
+
+impl Frontier for BinaryHeap<Reverse<(u64, usize)>> {
+    fn push(&mut self, priority: u64, state: usize) {
+        BinaryHeap::push(self, Reverse((priority, state)));
+    }
+
+    fn pop(&mut self) -> Option<(u64, usize)> {
+        BinaryHeap::pop(self).map(|Reverse(entry)| entry)
     }
 }
 
+/// Dial's bucket queue. Priorities only grow, and never more than `span` past the last one popped,
+/// so a ring of `span + 1` buckets is enough to hold every pending priority.
+#[derive(Debug)]
+struct BucketQueue {
+    buckets: Vec<Vec<usize>>,
+    current: u64,
+    len: usize,
+}
+
+impl BucketQueue {
+    /// Queue for priorities starting at `first`.
+    fn new(span: u64, first: u64) -> BucketQueue {
+        BucketQueue {
+            buckets: vec![vec![]; span as usize + 1],
+            current: first,
+            len: 0,
+        }
+    }
+}
+
+impl Frontier for BucketQueue {
+    fn push(&mut self, priority: u64, state: usize) {
+        let size = self.buckets.len() as u64;
+        assert!(
+            priority >= self.current && priority < self.current + size,
+            "{priority} {} {size}",
+            self.current
+        );
+
+        self.buckets[(priority % size) as usize].push(state);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(u64, usize)> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let size = self.buckets.len() as u64;
+        loop {
+            if let Some(state) = self.buckets[(self.current % size) as usize].pop() {
+                self.len -= 1;
+                return Some((self.current, state));
+            }
+            self.current += 1;
+        }
+    }
+}
+
+/// How the search is carried out. Every combination finds the same heat losses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Strategy {
+    // Dial's bucket queue instead of a binary heap.
+    buckets: bool,
+    // A* towards the targets, with the Manhattan distance times the cheapest block as heuristic.
+    a_star: bool,
+}
+
+/// Widest span of priorities the bucket queue is used for. Maps with costlier blocks would need
+/// too many buckets, so they go back to the binary heap.
+const MAX_BUCKETS: u64 = 1024;
+
+// On random maps the heuristic is too weak to make up for its own cost, so A* is opt-in.
+const DEFAULT_STRATEGY: Strategy = Strategy {
+    buckets: true,
+    a_star: false,
+};
+
 #[derive(Debug)]
 struct Map {
     width: usize,
     height: usize,
-    chars: Vec<u64>,
-
-    visited: Vec<(Direction, u64)>,
+    // Heat lost entering each block, moving North, South, East or West. `None` if the block can't
+    // be entered that way.
+    costs: Vec<[Option<u64>; 4]>,
 }
 
 impl Map {
@@ -54,8 +186,94 @@ impl Map {
         return Some(index as usize);
     }
 
+    /// Heat lost entering block `idx` moving in `direction`, if it can be entered that way.
+    fn cost(&self, idx: usize, direction: Direction) -> Option<u64> {
+        match direction {
+            Direction::None => Some(0),
+            Direction::North => self.costs[idx][0],
+            Direction::South => self.costs[idx][1],
+            Direction::East => self.costs[idx][2],
+            Direction::West => self.costs[idx][3],
+        }
+    }
+
+    /// Parses a heat loss map. Rows are either a digit per block, or comma separated costs for
+    /// larger values. In both cases `#` is a block that can't be entered, and with commas a block
+    /// can also be given as `n/e/s/w`, the costs of entering it moving North, East, South and West
+    /// (any of them can be `#`).
+    fn parse(buff: &str) -> Result<Map, String> {
+        let lines: Vec<&str> = buff
+            .split('\n')
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+        let separated = lines.iter().any(|line| line.contains(','));
+
+        let parse_cost = |cost: &str| -> Result<Option<u64>, String> {
+            match cost.trim() {
+                "#" => Ok(None),
+                cost => cost
+                    .parse::<u64>()
+                    .map(Some)
+                    .map_err(|_| format!("invalid cost `{cost}`")),
+            }
+        };
+
+        let mut costs = vec![];
+        let mut width = 0;
+        for (i, line) in lines.iter().enumerate() {
+            let cells: Vec<&str> = if separated {
+                line.split(',').collect()
+            } else {
+                line.split("").filter(|c| !c.is_empty()).collect()
+            };
+
+            if i == 0 {
+                width = cells.len();
+            } else if cells.len() != width {
+                return Err(format!(
+                    "row {} has {} blocks, expected {width}",
+                    i + 1,
+                    cells.len()
+                ));
+            }
+
+            for cell in cells {
+                let cell: Vec<&str> = cell.split('/').collect();
+                let block = match cell[..] {
+                    [cost] => [parse_cost(cost)?; 4],
+                    // Given clockwise, stored in Direction order.
+                    [n, e, s, w] if separated => [
+                        parse_cost(n)?,
+                        parse_cost(s)?,
+                        parse_cost(e)?,
+                        parse_cost(w)?,
+                    ],
+                    _ => {
+                        return Err(format!(
+                            "invalid block `{}` in row {}",
+                            cell.join("/"),
+                            i + 1
+                        ))
+                    }
+                };
+                costs.push(block);
+            }
+        }
+
+        if costs.is_empty() {
+            return Err("the map is empty".to_string());
+        }
+
+        return Ok(Map {
+            width,
+            height: lines.len(),
+            costs,
+        });
+    }
+
     fn to_point(&self, idx: usize) -> Option<(i64, i64)> {
-        if idx >= self.chars.len() {
+        if idx >= self.costs.len() {
             return None;
         }
 
@@ -65,542 +283,548 @@ impl Map {
         return Some((x as i64, y as i64));
     }
 
-    fn _print(&self) {
+    /// Draws the map with the blocks of the route replaced by the direction they were entered in.
+    fn print(&self, route: &Route) {
+        let mut path = vec![None; self.costs.len()];
+        for step in &route.steps {
+            path[step.idx] = Some(step.direction);
+        }
+
+        // Blocks are shown by their cost, `#` if they can't be entered, or `*` if it depends on the
+        // direction. Multi-digit costs get a column each.
+        let labels: Vec<String> = (0..self.costs.len())
+            .map(|idx| match self.costs[idx] {
+                [None, None, None, None] => "#".to_string(),
+                [first, rest @ ..] if rest.iter().all(|c| *c == first) => {
+                    first.unwrap().to_string()
+                }
+                _ => "*".to_string(),
+            })
+            .collect();
+        let len = labels.iter().map(|l| l.len()).max().unwrap_or(1);
+        let separator = if len > 1 { " " } else { "" };
+
         println!("Map {}x{}", self.width, self.height);
-        for i in 0..self.chars.len() {
-            if i != 0 && i % self.width as usize == 0 {
+        for (i, (label, entered)) in labels.iter().zip(path).enumerate() {
+            if i != 0 && i % self.width == 0 {
                 println!();
             }
 
-            let mut c: &str = &self.chars[i].to_string();
-            if self.visited[i] != (Direction::None, 0) {
-                c = match self.visited[i].0 {
-                    Direction::None => "o",
-                    Direction::North => "^",
-                    Direction::South => "v",
-                    Direction::East => ">",
-                    Direction::West => "<",
+            let c = match entered {
+                None => label.to_string(),
+                Some(Direction::None) => "o".to_string(),
+                Some(Direction::North) => "^".to_string(),
+                Some(Direction::South) => "v".to_string(),
+                Some(Direction::East) => ">".to_string(),
+                Some(Direction::West) => "<".to_string(),
+            };
+            print!("{separator}{c:>len$}");
+        }
+        println!("\n");
+    }
+
+    /// Prints the least heat loss to reach every block, or `.` if it can't be reached.
+    fn print_field(&self, field: &[Option<u64>]) {
+        let len = field.iter().flatten().max().unwrap_or(&0).to_string().len();
+        for row in field.chunks(self.width) {
+            for value in row {
+                match value {
+                    Some(heatloss) => print!(" {heatloss:>len$}"),
+                    None => print!(" {:>len$}", "."),
                 }
             }
-            print!("{}", c);
+            println!();
         }
-        println!("\n");
+        println!();
     }
 
-    fn _print_heatloss(&self) {
-        println!("Map {}x{}", self.width, self.height);
+    /// Lists every block of the route with its move and the heat lost so far.
+    fn print_steps(&self, route: &Route) {
+        for step in &route.steps {
+            let (x, y) = self.to_point(step.idx).unwrap();
+            println!("({x},{y}) {:?} -> {}", step.direction, step.heatloss);
+        }
+    }
+}
 
-        for i in 0..self.chars.len() {
-            if i != 0 && i % self.width as usize == 0 {
-                println!();
-            }
+/// Movement limits of a crucible. `min_straight` is the amount of blocks it has to move in a
+/// direction before it can turn, and `max_straight` the most it can move before it must turn.
+#[derive(Debug, Clone, Copy)]
+struct CrucibleRules {
+    min_straight: u64,
+    max_straight: u64,
+    // Whether turning back 180 degrees counts as a turn.
+    can_reverse: bool,
+    // Whether `min_straight` also has to be met to stop at the goal.
+    min_at_goal: bool,
+}
+
+const CRUCIBLE: CrucibleRules = CrucibleRules {
+    min_straight: 1,
+    max_straight: 3,
+    can_reverse: false,
+    min_at_goal: false,
+};
 
-            print!("{:03}|", self.visited[i].1);
+const ULTRA_CRUCIBLE: CrucibleRules = CrucibleRules {
+    min_straight: 4,
+    max_straight: 10,
+    can_reverse: false,
+    min_at_goal: true,
+};
+
+impl Direction {
+    fn offset(&self) -> (i64, i64) {
+        match self {
+            Direction::None => (0, 0),
+            Direction::North => (0, -1),
+            Direction::South => (0, 1),
+            Direction::East => (1, 0),
+            Direction::West => (-1, 0),
+        }
+    }
+
+    fn opposite(&self) -> Direction {
+        match self {
+            Direction::None => Direction::None,
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
         }
-        println!("\n");
     }
 }
 
+/// Command line options. By default, the crucible goes from the top-left block to the
+/// bottom-right one.
+#[derive(Debug, Default)]
+struct Options {
+    // `--path`: draw the best route to each destination.
+    show_path: bool,
+    // `--field`: print the least heat loss from the source to every block.
+    show_field: bool,
+    // `--from x,y`
+    source: Option<(i64, i64)>,
+    // `--to x,y`, can be given several times.
+    targets: Vec<(i64, i64)>,
+    // `--astar`: guide the search towards the targets.
+    a_star: bool,
+    // `--bench size`: compare the search strategies on a generated map.
+    bench: Option<usize>,
+}
+
+fn parse_options() -> Options {
+    let parse_point = |arg: Option<String>| -> (i64, i64) {
+        let arg = arg.expect("Missing a block position, like 3,4");
+        let (x, y) = arg
+            .split_once(',')
+            .expect("Block positions are given as x,y");
+        return (
+            x.trim().parse().expect("Invalid x coordinate"),
+            y.trim().parse().expect("Invalid y coordinate"),
+        );
+    };
+
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => options.show_path = true,
+            "--field" => options.show_field = true,
+            "--astar" => options.a_star = true,
+            "--from" => options.source = Some(parse_point(args.next())),
+            "--to" => options.targets.push(parse_point(args.next())),
+            "--bench" => {
+                let size = args.next().expect("Missing the size of the map");
+                options.bench = Some(size.parse().expect("Invalid map size"));
+            }
+            other => panic!("Unknown argument {other}"),
+        }
+    }
+
+    return options;
+}
+
 fn main() {
     println!("2023 AoC - Day 17");
 
+    let options = parse_options();
+    if let Some(size) = options.bench {
+        benchmark(size);
+        return;
+    }
+
     let mut buff = String::new();
     stdin()
         .read_to_string(&mut buff)
         .expect("Could not read stdin");
 
-    let buff: Vec<&str> = buff.split("\n").filter(|line| !line.is_empty()).collect();
-    let height = buff.len();
-    let width = buff[0].len();
+    let map = Map::parse(&buff).unwrap_or_else(|err| panic!("Invalid map: {err}"));
 
-    let mut chars: Vec<u64> = vec![];
-    for line in buff {
-        for char in line.chars() {
-            chars.push(char.to_digit(10).unwrap() as u64);
-        }
-    }
-
-    let visited = vec![(Direction::None, 0); chars.len()];
-    let mut map = Map {
-        width,
-        height,
-        chars,
-        visited,
+    let to_index = |(x, y): (i64, i64)| {
+        map.to_index(x, y)
+            .unwrap_or_else(|| panic!("Block {x},{y} is outside the map"))
     };
 
-    // Part 1 ---------------------------------------------------------------
-    //
+    let source = options.source.map(to_index).unwrap_or(0);
+    let targets: Vec<usize> = if options.targets.is_empty() {
+        vec![map.costs.len() - 1]
+    } else {
+        options.targets.iter().copied().map(to_index).collect()
+    };
 
-    let part1 = propagate_carts(&mut map);
-    println!("PART 1: {part1}");
+    for (part, rules) in [(1, CRUCIBLE), (2, ULTRA_CRUCIBLE)] {
+        // The distance field needs the whole map, otherwise stop once every target is reached.
+        let until: &[usize] = if options.show_field { &[] } else { &targets };
+        let strategy = Strategy {
+            a_star: options.a_star,
+            ..DEFAULT_STRATEGY
+        };
+        let search = propagate_carts(&map, &rules, source, until, strategy);
+
+        if options.show_field {
+            map.print_field(&search.distances());
+        }
 
-    // Part 2 ---------------------------------------------------------------
-    //
-    map.visited = vec![(Direction::None, 0); map.chars.len()];
+        for &target in &targets {
+            let (x, y) = map.to_point(target).unwrap();
+            let label = if options.targets.is_empty() {
+                String::new()
+            } else {
+                format!(" ({x},{y})")
+            };
+
+            match search.route(target) {
+                Some(route) => {
+                    if options.show_path {
+                        map.print(&route);
+                        map.print_steps(&route);
+                    }
+                    println!("PART {part}{label}: {}", route.heatloss);
+                }
+                None => println!("PART {part}{label}: can't be reached"),
+            }
+        }
+    }
+}
 
-    let part2 = propagate_carts_part2(&mut map);
-    println!("PART 2: {part2}");
+/// The outcome of a search from a source block. Every state settled by the search keeps its heat
+/// loss and the state it was reached from, along with the best state to stop at each block.
+#[derive(Debug)]
+struct Search {
+    space: StateSpace,
+    heatloss: Vec<u64>,
+    from: Vec<u32>,
+    closed: Vec<bool>,
+    stops: Vec<Option<usize>>,
 }
 
-fn propagate_carts(map: &mut Map) -> u64 {
-    // Add the two initial cells to the pusher.
-    use Direction::{East, North, South, West};
+impl Search {
+    /// Least heat loss route from the source to `target`, if the search reached it.
+    fn route(&self, target: usize) -> Option<Route> {
+        let last = self.stops[target]?;
+
+        // Follows the recorded predecessors back to the start.
+        let mut steps = vec![];
+        let mut index = last;
+        loop {
+            let (idx, direction, _) = self.space.state(index);
+            steps.push(PathStep {
+                idx,
+                direction,
+                heatloss: self.heatloss[index],
+            });
+
+            if direction == Direction::None {
+                break;
+            }
+            index = self.from[index] as usize;
+        }
+        steps.reverse();
 
-    // Each touple consists of (index, Traveling Direction, Previous count in this direction).
-    let start1 = Node {
-        idx: map.to_index(1, 0).unwrap(),
-        direction: East,
-        heatloss: map.chars[map.to_index(1, 0).unwrap()],
-        consecutive_cells: 1,
-    };
+        return Some(Route {
+            heatloss: self.heatloss[last],
+            steps,
+        });
+    }
 
-    let start2 = Node {
-        idx: map.to_index(0, 1).unwrap(),
-        direction: East,
-        heatloss: map.chars[map.to_index(0, 1).unwrap()],
-        consecutive_cells: 1,
-    };
+    /// Least heat loss to stop at each block of the map, if reachable.
+    fn distances(&self) -> Vec<Option<u64>> {
+        return self
+            .stops
+            .iter()
+            .map(|stop| stop.map(|index| self.heatloss[index]))
+            .collect();
+    }
+}
 
-    // BinaryHeap, seems to be a priority based vector. This makes it so that the system always
-    // extract the least heatloss cells from the neighboring node list.
-    let mut nodes = BinaryHeap::new();
-    nodes.push(start1);
-    nodes.push(start2);
+/// Moves a crucible from the `source` block under the given rules. Dijkstra (or A*) over the
+/// states (block, traveling direction, blocks moved in that direction), keeping the state each one
+/// was reached from to rebuild the paths. The search stops once every block in `targets` has been
+/// reached, or goes over the whole map if there are none.
+fn propagate_carts(
+    map: &Map,
+    rules: &CrucibleRules,
+    source: usize,
+    targets: &[usize],
+    strategy: Strategy,
+) -> Search {
+    let all_costs = map.costs.iter().flatten().flatten();
+    let cheapest = *all_costs.clone().min().unwrap_or(&0);
+    let priciest = *all_costs.max().unwrap_or(&0);
+
+    // Without targets there is nothing to aim for, so it's always Dijkstra.
+    let heuristic = |idx: usize| -> u64 {
+        if !strategy.a_star {
+            return 0;
+        }
 
-    // Already computed Hashset -> idx, Direction, Consecutive cells in that direction.
-    let mut cache: HashSet<(usize, Direction, u64)> = HashSet::new();
-    let mut count = 0;
+        let (x, y) = map.to_point(idx).unwrap();
+        let closest = targets
+            .iter()
+            .map(|&t| map.to_point(t).unwrap())
+            .map(|(tx, ty)| x.abs_diff(tx) + y.abs_diff(ty))
+            .min()
+            .unwrap_or(0);
+        return closest * cheapest;
+    };
 
-    // Get the next node -> priority lesser heatloss.
-    while let Some(node) = nodes.pop() {
-        let mut next_nodes: Vec<Node> = vec![];
-        count += 1;
+    // Each move adds at most the priciest block, and changes the heuristic by at most the
+    // cheapest one.
+    let span = priciest.saturating_add(cheapest);
+    if strategy.buckets && span < MAX_BUCKETS {
+        let queue = BucketQueue::new(span, heuristic(source));
+        return search_with(map, rules, source, targets, &heuristic, queue);
+    } else {
+        let queue = BinaryHeap::new();
+        return search_with(map, rules, source, targets, &heuristic, queue);
+    }
+}
 
-        let (x, y) = map.to_point(node.idx).unwrap();
+fn search_with(
+    map: &Map,
+    rules: &CrucibleRules,
+    source: usize,
+    targets: &[usize],
+    heuristic: &dyn Fn(usize) -> u64,
+    mut nodes: impl Frontier,
+) -> Search {
+    use Direction::{East, North, South, West};
+
+    let space = StateSpace::new(rules);
+    let states = map.costs.len() * space.per_block;
+    let mut search = Search {
+        space,
+        heatloss: vec![u64::MAX; states],
+        from: vec![u32::MAX; states],
+        closed: vec![false; states],
+        stops: vec![None; map.costs.len()],
+    };
 
-        // println!(
-        //     "{count}: Processing cell {},{}: Heat lost {} record for cell",
-        //     x, y, node.heatloss
-        // );
+    // The crucible starts without a direction, so it can leave towards any side.
+    let start = space.index((source, Direction::None, 0));
+    search.heatloss[start] = 0;
+    nodes.push(heuristic(source), start);
 
-        // Log the current heatloss record and direction.
-        if map.visited[node.idx].1 == 0 || map.visited[node.idx].1 > node.heatloss {
-            // println!("> New record for cell {} -> {}", node.idx, node.heatloss);
-            map.visited[node.idx] = (node.direction, node.heatloss);
-        }
+    let mut pending = targets.len();
 
-        // If the node corresponds to the exit point, by priority queue this is the least
-        // possible heatloss for this cell.
-        if node.idx == map.chars.len() - 1 {
-            // println!("> Reached the last cell");
-            return node.heatloss;
+    // Get the next state -> priority lesser heatloss (plus heuristic).
+    while let Some((_, index)) = nodes.pop() {
+        // The first time a state comes out of the queue is with its least heatloss, later copies
+        // are stale.
+        if search.closed[index] {
+            continue;
         }
+        search.closed[index] = true;
 
-        // Otherwise, get the neighboring cells and add them to the queue.
-
-        // Add the next posible cells to the node_list.
-        match node.direction {
-            North => {
-                // Always add 90 degree turn cells.
-                match map.to_index(x - 1, y) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: West,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
+        let (idx, direction, consecutive_cells) = space.state(index);
+        let heatloss = search.heatloss[index];
 
-                // Always add 90 degree turn cells.
-                match map.to_index(x + 1, y) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: East,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
+        // The first time the crucible can stop at a block is with the least possible heatloss
+        // for it. Check also if the crucible is allowed to stop.
+        let can_turn = consecutive_cells >= rules.min_straight;
+        if (can_turn || !rules.min_at_goal) && search.stops[idx].is_none() {
+            search.stops[idx] = Some(index);
 
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 3 {
-                    match map.to_index(x, y - 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: North,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
+            if targets.contains(&idx) {
+                pending -= 1;
+                if pending == 0 {
+                    break;
                 }
             }
-            South => {
-                // Always add 90 degree turn cells.
-                match map.to_index(x - 1, y) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: West,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
+        }
 
-                // Always add 90 degree turn cells.
-                match map.to_index(x + 1, y) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: East,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
+        // Otherwise, add the neighboring cells the crucible can move to.
+        let (x, y) = map.to_point(idx).unwrap();
+        for next_direction in [North, South, East, West] {
+            let straight = next_direction == direction;
+            let starting = direction == Direction::None;
 
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 3 {
-                    match map.to_index(x, y + 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: South,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
+            if straight && consecutive_cells >= rules.max_straight {
+                continue;
             }
-            East => {
-                // Always add 90 degree turn cells.
-                match map.to_index(x, y - 1) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: North,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
-
-                // Always add 90 degree turn cells.
-                match map.to_index(x, y + 1) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: South,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
-
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 3 {
-                    match map.to_index(x + 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: East,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
+            if !straight && !starting && !can_turn {
+                continue;
             }
-            West => {
-                // Always add 90 degree turn cells.
-                match map.to_index(x, y - 1) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: North,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
-
-                // Always add 90 degree turn cells.
-                match map.to_index(x, y + 1) {
-                    Some(val) => next_nodes.push(Node {
-                        idx: val,
-                        direction: South,
-                        heatloss: node.heatloss + map.chars[val],
-                        consecutive_cells: 1,
-                    }),
-                    None => {}
-                }
-
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 3 {
-                    match map.to_index(x - 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: West,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
+            if next_direction == direction.opposite() && !rules.can_reverse {
+                continue;
             }
-            _ => {}
-        }
 
-        // For each Node generated, insert them to the binary heap. The if block with the cache
-        // avoids inserting cells that were already computed.
-        for next_node in next_nodes {
-            if cache.insert((
-                next_node.idx,
-                next_node.direction,
-                next_node.consecutive_cells,
-            )) {
-                // let (x, y) = map.to_point(next_node.idx).unwrap();
-                // println!(
-                //     "> Adding ({x},{y} | {} | {}) to the nodes lists",
-                //     next_node.heatloss, next_node.consecutive_cells
-                // );
-                nodes.push(next_node);
+            let (dx, dy) = next_direction.offset();
+            let Some(val) = map.to_index(x + dx, y + dy) else {
+                continue;
+            };
+            let Some(cost) = map.cost(val, next_direction) else {
+                continue;
+            };
+
+            let run = if straight { consecutive_cells + 1 } else { 1 };
+            let next = space.index((val, next_direction, run));
+            let next_heatloss = heatloss + cost;
+
+            // Only queue states that improve on what's known of them.
+            if next_heatloss < search.heatloss[next] {
+                search.heatloss[next] = next_heatloss;
+                search.from[next] = index as u32;
+                nodes.push(next_heatloss + heuristic(val), next);
             }
         }
     }
 
-    // Return the minimum heatloss recorded at bottom right.
-    println!("Could not reach the last cell? |-> Iterations: {count}");
-    let i = map.chars.len() - 1;
-    return map.visited[i].1;
+    return search;
 }
 
-// Try the same thing, changing the next_nodes section?
-fn propagate_carts_part2(map: &mut Map) -> u64 {
-    // Add the two initial cells to the pusher.
-    use Direction::{East, North, South, West};
+/// Search node of the solver before the dense state arrays, kept as the benchmark baseline.
+#[derive(Debug, Eq, PartialEq)]
+struct Node {
+    idx: usize,
+    direction: Direction,
+    heatloss: u64,
+    consecutive_cells: u64,
+}
 
-    // Each touple consists of (index, Traveling Direction, Previous count in this direction).
-    let start1 = Node {
-        idx: map.to_index(1, 0).unwrap(),
-        direction: East,
-        heatloss: map.chars[map.to_index(1, 0).unwrap()],
-        consecutive_cells: 1,
-    };
+// Implementation of ordering by least heatloss, for the priority queue.
+impl Ord for Node {
+    fn cmp(&self, rhs: &Node) -> Ordering {
+        return rhs.heatloss.cmp(&self.heatloss);
+    }
+}
 
-    let start2 = Node {
-        idx: map.to_index(0, 1).unwrap(),
-        direction: East,
-        heatloss: map.chars[map.to_index(0, 1).unwrap()],
-        consecutive_cells: 1,
-    };
+impl PartialOrd for Node {
+    fn partial_cmp(&self, rhs: &Node) -> Option<Ordering> {
+        return Some(self.cmp(rhs));
+    }
+}
 
-    // BinaryHeap, seems to be a priority based vector. This makes it so that the system always
-    // extract the least heatloss cells from the neighboring node list.
-    let mut nodes = BinaryHeap::new();
-    nodes.push(start1);
-    nodes.push(start2);
+/// Least heat loss from `source` to `target` the way the solver used to find it: Dijkstra with a
+/// `BinaryHeap<Node>` and a `HashSet` of the states already settled.
+fn hashed_search(map: &Map, rules: &CrucibleRules, source: usize, target: usize) -> Option<u64> {
+    use Direction::{East, North, South, West};
 
-    // Already computed Hashset -> idx, Direction, Consecutive cells in that direction.
-    let mut cache: HashSet<(usize, Direction, u64)> = HashSet::new();
-    let mut count = 0;
+    let mut nodes = BinaryHeap::new();
+    nodes.push(Node {
+        idx: source,
+        direction: Direction::None,
+        heatloss: 0,
+        consecutive_cells: 0,
+    });
+    let mut cache: HashSet<State> = HashSet::new();
 
-    // Get the next node -> priority lesser heatloss.
     while let Some(node) = nodes.pop() {
-        let mut next_nodes: Vec<Node> = vec![];
-        count += 1;
-
-        let (x, y) = map.to_point(node.idx).unwrap();
-
-        // println!(
-        //     "{count}: Processing cell {},{}: Heat lost {} record for cell",
-        //     x, y, node.heatloss
-        // );
-
-        // Log the current heatloss record and direction.
-        if map.visited[node.idx].1 == 0 || map.visited[node.idx].1 > node.heatloss {
-            // println!("> New record for cell {} -> {}", node.idx, node.heatloss);
-            map.visited[node.idx] = (node.direction, node.heatloss);
+        if !cache.insert((node.idx, node.direction, node.consecutive_cells)) {
+            continue;
         }
 
-        // If the node corresponds to the exit point, by priority queue this is the least
-        // possible heatloss for this cell. Check also if the ultracrucible can stop here.
-        if node.idx == map.chars.len() - 1 && node.consecutive_cells > 3 {
-            println!("> Reached the last cell");
-            return node.heatloss;
+        let can_turn = node.consecutive_cells >= rules.min_straight;
+        if node.idx == target && (can_turn || !rules.min_at_goal) {
+            return Some(node.heatloss);
         }
 
-        // Otherwise, get the neighboring cells and add them to the queue.
-
-        // Add the next posible cells to the node_list.
-        match node.direction {
-            North => {
-                // If allowed to turn, add 90 degree turn cells.
-                if node.consecutive_cells > 3 {
-                    match map.to_index(x - 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: West,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
-
-                    match map.to_index(x + 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: East,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
-                }
+        let (x, y) = map.to_point(node.idx).unwrap();
+        for direction in [North, South, East, West] {
+            let straight = direction == node.direction;
+            let starting = node.direction == Direction::None;
 
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 10 {
-                    match map.to_index(x, y - 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: North,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
+            if straight && node.consecutive_cells >= rules.max_straight {
+                continue;
             }
-            South => {
-                // If allowed to turn, add 90 degree turn cells.
-                if node.consecutive_cells > 3 {
-                    match map.to_index(x - 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: West,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
-
-                    match map.to_index(x + 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: East,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
-                }
-
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 10 {
-                    match map.to_index(x, y + 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: South,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
+            if !straight && !starting && !can_turn {
+                continue;
+            }
+            if direction == node.direction.opposite() && !rules.can_reverse {
+                continue;
             }
-            East => {
-                // If allowed to turn, add 90 degree turn cells.
-                if node.consecutive_cells > 3 {
-                    match map.to_index(x, y - 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: North,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
 
-                    match map.to_index(x, y + 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: South,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
-                }
+            let (dx, dy) = direction.offset();
+            let Some(val) = map.to_index(x + dx, y + dy) else {
+                continue;
+            };
+            let Some(cost) = map.cost(val, direction) else {
+                continue;
+            };
+
+            nodes.push(Node {
+                idx: val,
+                direction,
+                heatloss: node.heatloss + cost,
+                consecutive_cells: if straight {
+                    node.consecutive_cells + 1
+                } else {
+                    1
+                },
+            });
+        }
+    }
 
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 10 {
-                    match map.to_index(x + 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: East,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
-            }
-            West => {
-                // If allowed to turn, add 90 degree turn cells.
-                if node.consecutive_cells > 3 {
-                    match map.to_index(x, y - 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: North,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
+    return None;
+}
 
-                    match map.to_index(x, y + 1) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: South,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: 1,
-                        }),
-                        None => {}
-                    }
-                }
+/// Times every strategy on a generated `size`x`size` map with random blocks from 1 to 9, along
+/// with the previous hash-based solver, checking they all agree.
+///
+/// On 300x300 the bucket queue is the fastest. A* is slower than plain Dijkstra there: the
+/// Manhattan distance times the cheapest block barely prunes anything on random maps, and costs
+/// a lookup per queued state.
+fn benchmark(size: usize) {
+    // Small xorshift generator, seeded so runs are comparable.
+    let mut seed: u64 = 0x2023_1217;
+    let mut costs = vec![];
+    for _ in 0..size * size {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        costs.push([Some(1 + seed % 9); 4]);
+    }
 
-                // If can still go straight, add the forward cell.
-                if node.consecutive_cells < 10 {
-                    match map.to_index(x - 1, y) {
-                        Some(val) => next_nodes.push(Node {
-                            idx: val,
-                            direction: West,
-                            heatloss: node.heatloss + map.chars[val],
-                            consecutive_cells: node.consecutive_cells + 1,
-                        }),
-                        None => {}
-                    }
-                }
-            }
-            _ => {}
+    let map = Map {
+        width: size,
+        height: size,
+        costs,
+    };
+    let targets = [map.costs.len() - 1];
+
+    for (part, rules) in [(1, CRUCIBLE), (2, ULTRA_CRUCIBLE)] {
+        let start = Instant::now();
+        let heatloss = hashed_search(&map, &rules, 0, targets[0]);
+        let elapsed = start.elapsed();
+        println!("{size}x{size} part {part}, binary heap and HashSet: {heatloss:?} in {elapsed:?}");
+
+        let mut results = vec![heatloss];
+        for (name, buckets, a_star) in [
+            ("binary heap, Dijkstra", false, false),
+            ("buckets, Dijkstra", true, false),
+            ("buckets, A*", true, true),
+        ] {
+            let strategy = Strategy { buckets, a_star };
+            let start = Instant::now();
+            let search = propagate_carts(&map, &rules, 0, &targets, strategy);
+            let elapsed = start.elapsed();
+
+            let heatloss = search.route(targets[0]).map(|r| r.heatloss);
+            println!("{size}x{size} part {part}, {name}: {heatloss:?} in {elapsed:?}");
+            results.push(heatloss);
         }
 
-        // For each Node generated, insert them to the binary heap. The if block with the cache
-        // avoids inserting cells that were already computed.
-        for next_node in next_nodes {
-            if cache.insert((
-                next_node.idx,
-                next_node.direction,
-                next_node.consecutive_cells,
-            )) {
-                // let (x, y) = map.to_point(next_node.idx).unwrap();
-                // println!(
-                //     "> Adding ({x},{y} | {} | {}) to the nodes lists",
-                //     next_node.heatloss, next_node.consecutive_cells
-                // );
-                nodes.push(next_node);
-            }
-        }
+        assert!(results.windows(2).all(|w| w[0] == w[1]));
     }
-
-    // Return the minimum heatloss recorded at bottom right.
-    println!("Could not reach the last cell? |-> Iterations: {count}");
-    let i = map.chars.len() - 1;
-    return map.visited[i].1;
 }
@@ -1,29 +1,485 @@
+#![allow(clippy::needless_return)]
+
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{stdin, Read},
 };
 
+/// Names of the attributes every part has, in the order their values are stored.
+#[derive(Debug, Clone)]
+struct Schema {
+    names: Vec<String>,
+}
+
+impl Schema {
+    /// Parses a declared schema, as a comma separated list of names like `x,m,a,s`.
+    fn declare(list: &str) -> Result<Schema, String> {
+        let mut schema = Schema { names: vec![] };
+        for name in list.split(',').map(|n| n.trim()) {
+            if name.is_empty() || schema.names.iter().any(|n| n == name) {
+                return Err(format!("Invalid attribute list `{list}`"));
+            }
+            schema.names.push(name.to_string());
+        }
+
+        return Ok(schema);
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
 #[derive(Debug)]
 struct Part {
-    //           x m a s
-    attribures: [u64; 4],
+    // One value per attribute, in schema order.
+    attributes: Vec<u64>,
+}
+
+/// A test on a single attribute value. Ranges are kept inclusive on both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less(u64),
+    LessEq(u64),
+    Greater(u64),
+    GreaterEq(u64),
+    Equal(u64),
+    NotEqual(u64),
+    InRange(u64, u64),
+}
+
+impl Comparison {
+    fn test(&self, value: u64) -> bool {
+        match *self {
+            Comparison::Less(t) => value < t,
+            Comparison::LessEq(t) => value <= t,
+            Comparison::Greater(t) => value > t,
+            Comparison::GreaterEq(t) => value >= t,
+            Comparison::Equal(t) => value == t,
+            Comparison::NotEqual(t) => value != t,
+            Comparison::InRange(lo, hi) => lo <= value && value <= hi,
+        }
+    }
+
+    /// The values that pass the test, as sorted and disjoint inclusive intervals.
+    fn intervals(&self) -> Vec<(u64, u64)> {
+        let below = |t: u64| t.checked_sub(1).map(|last| (0, last));
+        let above = |t: u64| t.checked_add(1).map(|first| (first, u64::MAX));
+
+        match *self {
+            Comparison::Less(t) => below(t).into_iter().collect(),
+            Comparison::LessEq(t) => vec![(0, t)],
+            Comparison::Greater(t) => above(t).into_iter().collect(),
+            Comparison::GreaterEq(t) => vec![(t, u64::MAX)],
+            Comparison::Equal(t) => vec![(t, t)],
+            Comparison::NotEqual(t) => below(t).into_iter().chain(above(t)).collect(),
+            Comparison::InRange(lo, hi) if lo <= hi => vec![(lo, hi)],
+            Comparison::InRange(..) => vec![],
+        }
+    }
+
+    /// The values that fail the test, as sorted and disjoint inclusive intervals.
+    fn rejected_intervals(&self) -> Vec<(u64, u64)> {
+        let mut rejected = vec![];
+        let mut next = Some(0);
+        for (lo, hi) in self.intervals() {
+            if let Some(first) = next.filter(|first| *first < lo) {
+                rejected.push((first, lo - 1));
+            }
+            next = hi.checked_add(1);
+        }
+
+        if let Some(first) = next {
+            rejected.push((first, u64::MAX));
+        }
+
+        return rejected;
+    }
+}
+
+/// The condition of a rule, a tree of attribute tests joined with `&&` and `||`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Test(usize, Comparison),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    fn test(&self, attributes: &[u64]) -> bool {
+        match self {
+            Condition::Test(attribute, comparison) => comparison.test(attributes[*attribute]),
+            Condition::And(a, b) => a.test(attributes) && b.test(attributes),
+            Condition::Or(a, b) => a.test(attributes) || b.test(attributes),
+        }
+    }
+
+    /// Splits a box into the points that pass the condition, and the ones that don't. Both lists
+    /// are disjoint boxes, and together they cover exactly the original box.
+    fn split(&self, rect: &HyperRect) -> (Vec<HyperRect>, Vec<HyperRect>) {
+        match self {
+            Condition::Test(attribute, comparison) => {
+                let restrict = |intervals: Vec<(u64, u64)>| -> Vec<HyperRect> {
+                    intervals
+                        .into_iter()
+                        .filter_map(|range| rect.restrict(*attribute, range))
+                        .collect()
+                };
+
+                (
+                    restrict(comparison.intervals()),
+                    restrict(comparison.rejected_intervals()),
+                )
+            }
+
+            Condition::And(a, b) => {
+                // Points that pass `a` still need to pass `b`.
+                let (passed, mut failed) = a.split(rect);
+                let mut both = vec![];
+                for p in passed {
+                    let (p_passed, p_failed) = b.split(&p);
+                    both.extend(p_passed);
+                    failed.extend(p_failed);
+                }
+                (both, failed)
+            }
+
+            Condition::Or(a, b) => {
+                // Points that fail `a` get a second chance with `b`.
+                let (mut passed, failed) = a.split(rect);
+                let mut neither = vec![];
+                for f in failed {
+                    let (f_passed, f_failed) = b.split(&f);
+                    passed.extend(f_passed);
+                    neither.extend(f_failed);
+                }
+                (passed, neither)
+            }
+        }
+    }
+
+    /// Formats the condition in the workflow language, like `a<2006 && (m>10 || x in 1..=5)`.
+    fn to_source(&self, schema: &Schema) -> String {
+        self.format(&mut |attribute, comparison| {
+            let name = &schema.names[attribute];
+            match comparison {
+                Comparison::Less(t) => format!("{name}<{t}"),
+                Comparison::LessEq(t) => format!("{name}<={t}"),
+                Comparison::Greater(t) => format!("{name}>{t}"),
+                Comparison::GreaterEq(t) => format!("{name}>={t}"),
+                Comparison::Equal(t) => format!("{name}=={t}"),
+                Comparison::NotEqual(t) => format!("{name}!={t}"),
+                Comparison::InRange(lo, hi) => format!("{name} in {lo}..={hi}"),
+            }
+        })
+    }
+
+    /// Formats the condition as a Rust expression over the fields of `part`.
+    fn to_rust(&self, schema: &Schema) -> String {
+        self.format(&mut |attribute, comparison| {
+            let name = &schema.names[attribute];
+            match comparison {
+                Comparison::Less(t) => format!("part.{name} < {t}"),
+                Comparison::LessEq(t) => format!("part.{name} <= {t}"),
+                Comparison::Greater(t) => format!("part.{name} > {t}"),
+                Comparison::GreaterEq(t) => format!("part.{name} >= {t}"),
+                Comparison::Equal(t) => format!("part.{name} == {t}"),
+                Comparison::NotEqual(t) => format!("part.{name} != {t}"),
+                Comparison::InRange(lo, hi) => format!("({lo}..={hi}).contains(&part.{name})"),
+            }
+        })
+    }
+
+    /// Joins the formatted tests with `&&` and `||`, adding parentheses only where `||` is nested
+    /// inside `&&`. Both languages share that precedence.
+    fn format(&self, test: &mut dyn FnMut(usize, Comparison) -> String) -> String {
+        match self {
+            Condition::Test(attribute, comparison) => test(*attribute, *comparison),
+            Condition::And(a, b) => {
+                let mut side = |c: &Condition| match c {
+                    Condition::Or(..) => format!("({})", c.format(test)),
+                    _ => c.format(test),
+                };
+                let a = side(a);
+                format!("{a} && {}", side(b))
+            }
+            Condition::Or(a, b) => format!("{} || {}", a.format(test), b.format(test)),
+        }
+    }
+
+    fn attributes_mut(&mut self, visit: &mut dyn FnMut(&mut usize)) {
+        match self {
+            Condition::Test(attribute, _) => visit(attribute),
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.attributes_mut(visit);
+                b.attributes_mut(visit);
+            }
+        }
+    }
+}
+
+/// Recursive descent parser for rule conditions:
+///
+/// ```text
+/// or   := and ("||" and)*
+/// and  := test ("&&" test)*
+/// test := "(" or ")" | name op number | name "in" number (".." | "..=") number
+/// op   := "<" | "<=" | ">" | ">=" | "==" | "!="
+/// ```
+///
+/// Attribute names are collected in `names`, and tests refer to them by index.
+struct ConditionParser<'a, 'n> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    names: &'n mut Vec<&'a str>,
+}
+
+impl<'a, 'n> ConditionParser<'a, 'n> {
+    fn parse(text: &'a str, names: &'n mut Vec<&'a str>) -> Result<Condition, String> {
+        let mut parser = ConditionParser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            names,
+        };
+
+        let condition = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected `{token}` in condition `{text}`"));
+        }
+
+        return Ok(condition);
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or("unexpected end of condition")?;
+        self.pos += 1;
+        return Ok(token);
+    }
+
+    fn eat(&mut self, expected: &str) -> bool {
+        if self.tokens.get(self.pos) == Some(&expected) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        let token = self.next()?;
+        return token
+            .parse::<u64>()
+            .map_err(|_| format!("expected a number, found `{token}`"));
+    }
+
+    fn parse_or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.parse_and()?;
+        while self.eat("||") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.parse_and()?));
+        }
+        return Ok(condition);
+    }
+
+    fn parse_and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.parse_test()?;
+        while self.eat("&&") {
+            condition = Condition::And(Box::new(condition), Box::new(self.parse_test()?));
+        }
+        return Ok(condition);
+    }
+
+    fn parse_test(&mut self) -> Result<Condition, String> {
+        if self.eat("(") {
+            let condition = self.parse_or()?;
+            if !self.eat(")") {
+                return Err("missing `)` in condition".to_string());
+            }
+            return Ok(condition);
+        }
+
+        let name = self.next()?;
+        if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("expected an attribute name, found `{name}`"));
+        }
+
+        let attribute = match self.names.iter().position(|n| *n == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name);
+                self.names.len() - 1
+            }
+        };
+
+        let comparison = match self.next()? {
+            "<" => Comparison::Less(self.number()?),
+            "<=" => Comparison::LessEq(self.number()?),
+            ">" => Comparison::Greater(self.number()?),
+            ">=" => Comparison::GreaterEq(self.number()?),
+            "==" => Comparison::Equal(self.number()?),
+            "!=" => Comparison::NotEqual(self.number()?),
+            "in" => {
+                let lo = self.number()?;
+                match self.next()? {
+                    // Same as Rust ranges, `..` leaves the end out.
+                    ".." => match self.number()?.checked_sub(1) {
+                        Some(hi) => Comparison::InRange(lo, hi),
+                        None => Comparison::InRange(1, 0),
+                    },
+                    "..=" => Comparison::InRange(lo, self.number()?),
+                    token => return Err(format!("expected `..` or `..=`, found `{token}`")),
+                }
+            }
+            token => return Err(format!("unknown comparison `{token}`")),
+        };
+
+        return Ok(Condition::Test(attribute, comparison));
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<&str>, String> {
+    const SYMBOLS: [&str; 13] = [
+        "..=", "..", "<=", ">=", "==", "!=", "&&", "||", "<", ">", "(", ")", "=",
+    ];
+
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let len = if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            symbol.len()
+        } else {
+            let word = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if word == 0 {
+                return Err(format!("unexpected character in condition `{text}`"));
+            }
+            word
+        };
+
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+
+    return Ok(tokens);
 }
 
 #[derive(Debug)]
 struct Rule {
-    attribute: usize,
-    operation: char,
-    threshold: u64,
+    condition: Condition,
     target: String,
+    // Column of the input line where the rule starts.
+    column: usize,
 }
 
 #[derive(Debug)]
 struct Workflow {
     ruleset: Vec<Rule>,
     default: String,
+    // Line of the input where the workflow is defined, and column of the default target.
+    line: usize,
+    default_column: usize,
+}
+
+impl Workflow {
+    /// Every (target, column) this workflow can send parts to, the default being the last one.
+    fn targets(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.ruleset
+            .iter()
+            .map(|rule| (rule.target.as_str(), rule.column))
+            .chain(std::iter::once((
+                self.default.as_str(),
+                self.default_column,
+            )))
+    }
+
+    /// Splits the points of `region` between the rules, each taking the ones that pass it out of
+    /// those the rules before it left. Returns the boxes taken by each rule, followed by the ones
+    /// left for the default, in the same order as `targets`.
+    fn partition(&self, region: Vec<HyperRect>) -> Vec<Vec<HyperRect>> {
+        let mut parts = vec![];
+        let mut leftover = region;
+        for rule in &self.ruleset {
+            let mut passed = vec![];
+            let mut failed = vec![];
+            for rect in &leftover {
+                let (r_passed, r_failed) = rule.condition.split(rect);
+                passed.extend(r_passed);
+                failed.extend(r_failed);
+            }
+            parts.push(passed);
+            leftover = failed;
+        }
+        parts.push(leftover);
+
+        return parts;
+    }
 }
 
-fn parse_input() -> (HashMap<String, Workflow>, Vec<Part>) {
+/// All possible parts have attributes in the range 1 to 4000.
+const DOMAIN: (u64, u64) = (1, 4000);
+
+type Input = (Schema, HashMap<String, Workflow>, Vec<Part>);
+
+/// Parses the `name=value` list of a part like `{x=787,m=2655}`, without checking the names.
+fn parse_part_values(part: &str) -> Result<Vec<(&str, u64)>, String> {
+    // Trim the {} at start and end
+    let part = part
+        .trim()
+        .strip_prefix('{')
+        .and_then(|p| p.strip_suffix('}'))
+        .ok_or("part should be enclosed in {}")?;
+
+    let mut values = vec![];
+    for attribute in part.split(',') {
+        let (name, value) = attribute
+            .split_once('=')
+            .ok_or(format!("expected name=value, found `{attribute}`"))?;
+        let value = value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid value for `{name}`: `{value}`"))?;
+        values.push((name.trim(), value));
+    }
+
+    return Ok(values);
+}
+
+impl Part {
+    /// Sorts the values in schema order. Every attribute of the schema needs exactly one value,
+    /// within the domain the workflows are checked on.
+    fn new(schema: &Schema, values: Vec<(&str, u64)>) -> Result<Part, String> {
+        let mut attributes: Vec<Option<u64>> = vec![None; schema.names.len()];
+        for (name, value) in values {
+            let (lo, hi) = DOMAIN;
+            if value < lo || value > hi {
+                return Err(format!(
+                    "`{name}` is {value}, outside the range {lo} to {hi}"
+                ));
+            }
+
+            let index = schema
+                .index(name)
+                .ok_or(format!("unknown attribute `{name}`"))?;
+            if attributes[index].replace(value).is_some() {
+                return Err(format!("attribute `{name}` given twice"));
+            }
+        }
+
+        let attributes = attributes
+            .iter()
+            .zip(&schema.names)
+            .map(|(value, name)| value.ok_or(format!("missing a value for `{name}`")))
+            .collect::<Result<Vec<u64>, String>>()?;
+
+        return Ok(Part { attributes });
+    }
+}
+
+/// Reads workflows and parts from stdin. The attribute schema is `declared`, or otherwise taken from
+/// the first part (or from the rules, in order of appearance, if there are no parts).
+fn parse_input(declared: Option<Schema>) -> Result<Input, String> {
     let mut workflows = HashMap::new();
     let mut part_list: Vec<Part> = vec![];
 
@@ -33,308 +489,837 @@ fn parse_input() -> (HashMap<String, Workflow>, Vec<Part>) {
         .expect("Could not read stdin!");
 
     // Split on the empty line to separate rules from parts description.
-    let workf_buff = buff.split("\n\n").nth(0).unwrap();
-    let parts_buff = buff.split("\n\n").nth(1).unwrap();
+    let workf_buff = buff.split("\n\n").next().unwrap();
+    let parts_buff = buff.split("\n\n").nth(1).unwrap_or("");
+    let parts_first_line = workf_buff.lines().count() + 2;
+
+    // Parse parts first, as (name, value) lists, so they can define the schema.
+    let mut raw_parts: Vec<(usize, Vec<(&str, u64)>)> = vec![];
+    for (i, part) in parts_buff.split('\n').enumerate() {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        let line = parts_first_line + i;
+
+        let values = parse_part_values(part).map_err(|err| format!("Line {line}: {err}"))?;
+        raw_parts.push((line, values));
+    }
 
-    // Parse workflows:
-    for workflow in workf_buff.split("\n") {
+    // Parse workflows. Until the schema is known, rule attributes index into `rule_names`.
+    let mut rule_names: Vec<&str> = vec![];
+    for (i, workflow) in workf_buff.split('\n').enumerate() {
+        let indent = workflow.len() - workflow.trim_start().len();
         let workflow = workflow.trim();
         if workflow.is_empty() {
             continue;
         }
+        let line = i + 1;
 
-        let name = workflow.split("{").nth(0).unwrap().to_string();
-        let rules = workflow.split("{").nth(1).unwrap();
+        let name = workflow.split('{').next().unwrap().to_string();
+        let rules = workflow.split('{').nth(1).unwrap();
         let rules = &rules[0..rules.len() - 1];
 
         let mut ruleset: Vec<Rule> = vec![];
         let mut default: String = "R".to_string();
-        for rule in rules.split(",") {
-            if rule.contains(":") {
-                let operation = rule.chars().nth(1).unwrap();
-                let attribute = rule.split(['<', '>', ':']).nth(0).unwrap();
-                let threshold = rule.split(['<', '>', ':']).nth(1).unwrap();
-                let target = rule.split(['<', '>', ':']).nth(2).unwrap();
-
+        let mut column = indent + name.len() + 2;
+        let mut default_column = column;
+        for rule in rules.split(',') {
+            if let Some((condition, target)) = rule.rsplit_once(':') {
                 // Parse each element of the Rule
-                let attribute = match attribute {
-                    "x" => 0,
-                    "m" => 1,
-                    "a" => 2,
-                    "s" => 3,
-                    _ => 9,
-                };
-
-                let threshold = threshold.parse::<u64>().unwrap();
-                let target = target.to_string();
+                let condition = ConditionParser::parse(condition, &mut rule_names)
+                    .map_err(|err| format!("Line {line}: {err}"))?;
+                let target = target.trim().to_string();
 
                 ruleset.push(Rule {
-                    attribute,
-                    operation,
-                    threshold,
+                    condition,
                     target,
+                    column,
                 });
             } else {
-                default = rule.to_string();
+                default = rule.trim().to_string();
+                default_column = column;
             }
+            column += rule.len() + 1;
+        }
+
+        if let Some(previous) = workflows.get(&name).map(|wf: &Workflow| wf.line) {
+            return Err(format!(
+                "Line {line}: workflow `{name}` was already defined on line {previous}"
+            ));
         }
 
-        workflows.insert(name, Workflow { ruleset, default });
+        workflows.insert(
+            name,
+            Workflow {
+                ruleset,
+                default,
+                line,
+                default_column,
+            },
+        );
     }
 
-    // Parse parts:
-    for part in parts_buff.split("\n") {
-        let part = part.trim();
-        if part.is_empty() {
-            continue;
+    let schema = match declared {
+        Some(schema) => schema,
+        None => {
+            let mut schema = Schema { names: vec![] };
+            let names: Vec<&str> = match raw_parts.first() {
+                Some((_, values)) => values.iter().map(|(name, _)| *name).collect(),
+                None => rule_names.clone(),
+            };
+            for name in names {
+                if schema.index(name).is_none() {
+                    schema.names.push(name.to_string());
+                }
+            }
+            schema
         }
+    };
 
-        // Trim the {} at start and end
-        let part = &part[1..part.len() - 1];
+    // Map every rule attribute to its index in the schema, in input order to report the first error.
+    let mut by_line: Vec<(&String, &mut Workflow)> = workflows.iter_mut().collect();
+    by_line.sort_by_key(|(_, wf)| wf.line);
+    for (wf_name, wf) in by_line {
+        for rule in wf.ruleset.iter_mut() {
+            let mut unknown = None;
+            rule.condition.attributes_mut(&mut |attribute| {
+                let name = rule_names[*attribute];
+                match schema.index(name) {
+                    Some(index) => *attribute = index,
+                    None => unknown = unknown.or(Some(name)),
+                }
+            });
 
-        // Get attributes by index: x = 0, m = 1, a = 2, s = 3.
-        // Assuming all attributes are given in order, and all parts have all attributes
-        let x = part.split(",").nth(0).unwrap().split("=").nth(1).unwrap();
-        let m = part.split(",").nth(1).unwrap().split("=").nth(1).unwrap();
-        let a = part.split(",").nth(2).unwrap().split("=").nth(1).unwrap();
-        let s = part.split(",").nth(3).unwrap().split("=").nth(1).unwrap();
-        let x = x.parse::<u64>().unwrap();
-        let m = m.parse::<u64>().unwrap();
-        let a = a.parse::<u64>().unwrap();
-        let s = s.parse::<u64>().unwrap();
+            if let Some(name) = unknown {
+                return Err(format!(
+                    "Line {}: unknown attribute `{name}` in workflow `{wf_name}`",
+                    wf.line
+                ));
+            }
+        }
+    }
 
-        part_list.push(Part {
-            attribures: [x, m, a, s],
-        })
+    for (line, values) in raw_parts {
+        let part = Part::new(&schema, values).map_err(|err| format!("Line {line}: {err}"))?;
+        part_list.push(part);
     }
 
-    return (workflows, part_list);
+    return Ok((schema, workflows, part_list));
 }
 
-fn process_part1(workflows: &HashMap<String, Workflow>, parts: Vec<Part>) -> u64 {
-    let mut approved: Vec<Part> = vec![];
-    'next_part: for part in parts {
-        // Initial point.
-        let mut wf = workflows.get("in").unwrap();
-
-        'next_workflow: loop {
-            let mut next_workflow = wf.default.as_str();
-            for rule in &wf.ruleset {
-                // Evaluate the rule:
-                let passed = match rule.operation {
-                    '>' => part.attribures[rule.attribute] > rule.threshold,
-                    '<' => part.attribures[rule.attribute] < rule.threshold,
-                    _ => false,
-                };
+/// One step of a part through the workflows: the workflow, and the rule that sent the part on, or
+/// `None` if it went to the default target.
+#[derive(Debug, Clone, Copy)]
+struct Step<'a> {
+    workflow: &'a str,
+    rule: Option<&'a Rule>,
+}
 
-                if passed {
-                    next_workflow = rule.target.as_str();
-                    break;
-                }
-            }
-            // No more rules or passed a test, go to the next_workflow
-            match next_workflow {
-                "A" => {
-                    approved.push(part);
-                    continue 'next_part;
-                }
-                "R" => continue 'next_part,
-                val => {
-                    wf = workflows.get(val).unwrap();
-                    continue 'next_workflow;
-                }
-            }
+/// Formats a route like `in[s<1351] -> px[a<2006] -> qkq -> A`.
+fn format_route(schema: &Schema, steps: &[Step], end: &str) -> String {
+    let mut route = String::new();
+    for step in steps {
+        route.push_str(step.workflow);
+        if let Some(rule) = step.rule {
+            route.push_str(&format!("[{}]", rule.condition.to_source(schema)));
+        }
+        route.push_str(" -> ");
+    }
+    route.push_str(end);
+
+    return route;
+}
+
+/// Sends a part through the workflows starting at `in`. Returns every step taken, and where the
+/// part ended, `A` or `R`.
+fn route<'a>(workflows: &'a HashMap<String, Workflow>, part: &Part) -> (Vec<Step<'a>>, &'a str) {
+    let mut steps = vec![];
+    let (mut name, mut wf) = workflows.get_key_value("in").unwrap();
+
+    loop {
+        let rule = wf
+            .ruleset
+            .iter()
+            .find(|rule| rule.condition.test(&part.attributes));
+        steps.push(Step {
+            workflow: name.as_str(),
+            rule,
+        });
+
+        // No more rules or passed a test, go to the next_workflow
+        let next_workflow = rule.map(|r| &r.target).unwrap_or(&wf.default);
+        match next_workflow.as_str() {
+            end @ ("A" | "R") => return (steps, end),
+            val => (name, wf) = workflows.get_key_value(val).unwrap(),
         }
     }
+}
+
+fn process_part1(workflows: &HashMap<String, Workflow>, parts: Vec<Part>) -> u64 {
+    let approved = parts.iter().filter(|part| route(workflows, part).1 == "A");
 
     // Return the sum of all approved attributes.
     return approved
-        .iter()
-        .map(|part| part.attribures.iter().sum::<u64>())
+        .map(|part| part.attributes.iter().sum::<u64>())
         .sum::<u64>();
 }
 
-fn approved_permutations(
-    workflows: &HashMap<String, Workflow>,
+/// An axis-aligned box of integer points, with an inclusive range of values per dimension. The box
+/// is empty when any of its ranges ends before it starts. The amount of dimensions is given by the
+/// attribute schema, so it's only known at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HyperRect {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl HyperRect {
+    fn new(ranges: Vec<(u64, u64)>) -> Self {
+        HyperRect { ranges }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.iter().any(|(lo, hi)| lo > hi)
+    }
+
+    /// Amount of integer points inside the box, `None` if it doesn't fit in 128 bits.
+    fn volume(&self) -> Option<u128> {
+        if self.is_empty() {
+            return Some(0);
+        }
+
+        return self.ranges.iter().try_fold(1u128, |acc, (lo, hi)| {
+            acc.checked_mul(1 + (hi - lo) as u128)
+        });
+    }
+
+    /// Splits the box along `axis` into the points below `threshold`, and the ones at or above it.
+    /// Either side may come out empty.
+    fn split(&self, axis: usize, threshold: u64) -> (Self, Self) {
+        let (lo, hi) = self.ranges[axis];
+        let mut below = self.clone();
+        let mut above = self.clone();
+
+        below.ranges[axis] = match threshold.checked_sub(1) {
+            Some(last) => (lo, hi.min(last)),
+            None => (1, 0),
+        };
+        above.ranges[axis] = (lo.max(threshold), hi);
+
+        return (below, above);
+    }
+
+    /// The part of the box whose value along `axis` is within `range`.
+    fn restrict(&self, axis: usize, range: (u64, u64)) -> Option<Self> {
+        let (lo, hi) = self.ranges[axis];
+        let mut rect = self.clone();
+        rect.ranges[axis] = (lo.max(range.0), hi.min(range.1));
+
+        return if rect.is_empty() { None } else { Some(rect) };
+    }
+
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        let mut ranges = self.ranges.clone();
+        for (range, (lo, hi)) in ranges.iter_mut().zip(&other.ranges) {
+            *range = (range.0.max(*lo), range.1.min(*hi));
+        }
+
+        let rect = HyperRect { ranges };
+        return if rect.is_empty() { None } else { Some(rect) };
+    }
+
+    /// The parts of this box not covered by `other`, as disjoint boxes. Slabs are cut off one axis
+    /// at a time, so at most two boxes come out per dimension.
+    fn difference(&self, other: &Self) -> Vec<Self> {
+        let Some(common) = self.intersection(other) else {
+            return vec![self.clone()];
+        };
+
+        let mut pieces = vec![];
+        let mut rest = self.clone();
+        for (axis, (lo, hi)) in common.ranges.iter().enumerate() {
+            let (below, inside) = rest.split(axis, *lo);
+            let (inside, above) = inside.split(axis, hi + 1);
+
+            pieces.extend([below, above].into_iter().filter(|r| !r.is_empty()));
+            rest = inside;
+        }
+
+        return pieces;
+    }
+}
+
+/// A union of pairwise disjoint boxes, so its volume is just the sum of theirs.
+#[derive(Debug, Clone, Default)]
+struct BoxSet {
+    rects: Vec<HyperRect>,
+}
+
+impl BoxSet {
+    /// Adds the points of `rect` not already in the set.
+    fn union(&mut self, rect: HyperRect) {
+        let mut pieces = vec![rect];
+        for existing in &self.rects {
+            pieces = pieces.iter().flat_map(|p| p.difference(existing)).collect();
+        }
+
+        self.rects
+            .extend(pieces.into_iter().filter(|r| !r.is_empty()));
+    }
+
+    fn volume(&self) -> Option<u128> {
+        return total_volume(&self.rects);
+    }
+}
+
+/// Sum of the volumes of some boxes, `None` if it doesn't fit in 128 bits.
+fn total_volume<'a>(rects: impl IntoIterator<Item = &'a HyperRect>) -> Option<u128> {
+    return rects
+        .into_iter()
+        .try_fold(0u128, |acc, r| acc.checked_add(r.volume()?));
+}
+
+/// Walks the workflows starting at `wf_name` with the points in `region`, adding every box of points
+/// that ends up approved to `accepted`, along with the steps that took it there.
+fn approved_region<'a>(
+    workflows: &'a HashMap<String, Workflow>,
     wf_name: &str,
-    ranges: ((u64, u64), (u64, u64), (u64, u64), (u64, u64)),
-) -> u64 {
-    let mut leftover_ranges = ranges.clone();
-    let mut sum: u64 = 0;
-
-    let wf = workflows.get(wf_name).expect("Could not find workflow");
-
-    // Calculate the ranges that split into each workflow
-    for rule in &wf.ruleset {
-        let mut new_ranges = ((0, 0), (0, 0), (0, 0), (0, 0));
-        match rule.attribute {
-            0 => match rule.operation {
-                '>' => {
-                    new_ranges = (
-                        (rule.threshold + 1, leftover_ranges.0 .1),
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                    leftover_ranges = (
-                        (leftover_ranges.0 .0, rule.threshold),
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                }
-                '<' => {
-                    new_ranges = (
-                        (leftover_ranges.0 .0, rule.threshold - 1),
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                    leftover_ranges = (
-                        (rule.threshold, leftover_ranges.0 .1),
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                }
-                _ => {}
-            },
-            1 => match rule.operation {
-                '>' => {
-                    new_ranges = (
-                        leftover_ranges.0,
-                        (rule.threshold + 1, leftover_ranges.1 .1),
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                    leftover_ranges = (
-                        leftover_ranges.0,
-                        (leftover_ranges.1 .0, rule.threshold),
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                }
-                '<' => {
-                    new_ranges = (
-                        leftover_ranges.0,
-                        (leftover_ranges.1 .0, rule.threshold - 1),
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
-                    leftover_ranges = (
-                        leftover_ranges.0,
-                        (rule.threshold, leftover_ranges.1 .1),
-                        leftover_ranges.2,
-                        leftover_ranges.3,
-                    );
+    region: HyperRect,
+    steps: &mut Vec<Step<'a>>,
+    accepted: &mut Vec<(HyperRect, Vec<Step<'a>>)>,
+) {
+    let (name, wf) = workflows
+        .get_key_value(wf_name)
+        .expect("Could not find workflow");
+
+    let mut send = |step: Step<'a>, target: &str, rect: HyperRect| {
+        steps.push(step);
+        match target {
+            "A" => accepted.push((rect, steps.clone())),
+            "R" => {}
+            val => approved_region(workflows, val, rect, steps, accepted),
+        }
+        steps.pop();
+    };
+
+    // Each rule takes the points that pass it, the rest go on to the next rule.
+    let rules = wf.ruleset.iter().map(Some).chain([None]);
+    for ((rule, (target, _)), rects) in rules.zip(wf.targets()).zip(wf.partition(vec![region])) {
+        let step = Step {
+            workflow: name.as_str(),
+            rule,
+        };
+        for rect in rects {
+            send(step, target, rect);
+        }
+    }
+}
+
+/// Every disjoint box of accepted parts, with the route that accepts it.
+fn accepted_regions<'a>(
+    schema: &Schema,
+    workflows: &'a HashMap<String, Workflow>,
+) -> Vec<(HyperRect, Vec<Step<'a>>)> {
+    let initial_region = HyperRect::new(vec![DOMAIN; schema.names.len()]);
+
+    let mut accepted = vec![];
+    approved_region(workflows, "in", initial_region, &mut vec![], &mut accepted);
+
+    return accepted;
+}
+
+fn process_part2(schema: &Schema, workflows: &HashMap<String, Workflow>) -> Result<u128, String> {
+    let mut accepted = BoxSet::default();
+    for (rect, _) in accepted_regions(schema, workflows) {
+        accepted.union(rect);
+    }
+
+    return accepted
+        .volume()
+        .ok_or_else(|| "the accepted combinations don't fit in 128 bits".to_string());
+}
+
+/// The workflows inlined into a single binary decision tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Decision {
+    Accept,
+    Reject,
+    Branch(Condition, Box<Decision>, Box<Decision>),
+}
+
+impl Decision {
+    /// Builds the decision tree for the parts in `region` that are sent to `target`. Branches that
+    /// no part can take are left out, and the rest is simplified as it's built.
+    fn compile(
+        workflows: &HashMap<String, Workflow>,
+        target: &str,
+        region: Vec<HyperRect>,
+    ) -> Self {
+        match target {
+            "A" => Decision::Accept,
+            "R" => Decision::Reject,
+            name => {
+                let wf = &workflows[name];
+                let mut parts = wf.partition(region);
+
+                // Built from the default back to the first rule. A test that always gives the
+                // same answer here is not needed, so branches no part takes are left out.
+                let left = parts.pop().unwrap();
+                let mut decision =
+                    (!left.is_empty()).then(|| Decision::compile(workflows, &wf.default, left));
+                for (rule, passed) in wf.ruleset.iter().zip(parts).rev() {
+                    if passed.is_empty() {
+                        continue;
+                    }
+
+                    let then = Decision::compile(workflows, &rule.target, passed);
+                    decision = Some(match decision {
+                        Some(otherwise) => {
+                            Decision::branch(rule.condition.clone(), then, otherwise)
+                        }
+                        None => then,
+                    });
                 }
-                _ => {}
-            },
-            2 => match rule.operation {
-                '>' => {
-                    new_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        (rule.threshold + 1, leftover_ranges.2 .1),
-                        leftover_ranges.3,
-                    );
-                    leftover_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        (leftover_ranges.2 .0, rule.threshold),
-                        leftover_ranges.3,
-                    );
+
+                // No part gets here.
+                decision.unwrap_or(Decision::Reject)
+            }
+        }
+    }
+
+    /// Creates a branch, merging it with its children when they lead to the same place.
+    fn branch(condition: Condition, then: Decision, otherwise: Decision) -> Self {
+        // Both ways end up the same, so there's nothing to decide.
+        if then == otherwise {
+            return then;
+        }
+
+        match (then, otherwise) {
+            // `c1 ? X : (c2 ? X : Y)` is `(c1 || c2) ? X : Y`.
+            (then, Decision::Branch(c2, t2, e2)) if *t2 == then => {
+                let condition = Condition::Or(Box::new(condition), Box::new(c2));
+                Decision::branch(condition, then, *e2)
+            }
+            // `c1 ? (c2 ? X : Y) : Y` is `(c1 && c2) ? X : Y`.
+            (Decision::Branch(c2, t2, e2), otherwise) if *e2 == otherwise => {
+                let condition = Condition::And(Box::new(condition), Box::new(c2));
+                Decision::branch(condition, *t2, otherwise)
+            }
+            (then, otherwise) => Decision::Branch(condition, Box::new(then), Box::new(otherwise)),
+        }
+    }
+
+    fn count_branches(&self) -> usize {
+        match self {
+            Decision::Branch(_, t, e) => 1 + t.count_branches() + e.count_branches(),
+            _ => 0,
+        }
+    }
+
+    /// Graphviz view of the tree. Every branch is a box with its condition, leading to the shared
+    /// A and R nodes.
+    fn to_dot(&self, schema: &Schema) -> String {
+        fn visit(
+            decision: &Decision,
+            schema: &Schema,
+            next_id: &mut usize,
+            out: &mut String,
+        ) -> String {
+            let (condition, then, otherwise) = match decision {
+                Decision::Accept => return "A".to_string(),
+                Decision::Reject => return "R".to_string(),
+                Decision::Branch(c, t, e) => (c, t, e),
+            };
+
+            let id = format!("n{next_id}");
+            *next_id += 1;
+
+            let label = condition.to_source(schema);
+            out.push_str(&format!("    {id} [shape=box, label=\"{label}\"];\n"));
+            let then = visit(then, schema, next_id, out);
+            let otherwise = visit(otherwise, schema, next_id, out);
+            out.push_str(&format!("    {id} -> {then} [label=\"true\"];\n"));
+            out.push_str(&format!(
+                "    {id} -> {otherwise} [label=\"false\", style=dashed];\n"
+            ));
+
+            return id;
+        }
+
+        let mut out = String::from("digraph accept {\n");
+        out.push_str("    A [shape=doublecircle, color=green];\n");
+        out.push_str("    R [shape=doublecircle, color=red];\n");
+        visit(self, schema, &mut 0, &mut out);
+        out.push_str("}\n");
+
+        return out;
+    }
+
+    /// Rust source with a `Part` struct and a single `accept` function for it.
+    fn to_rust(&self, schema: &Schema) -> String {
+        fn visit(decision: &Decision, schema: &Schema, depth: usize, out: &mut String) {
+            let indent = "    ".repeat(depth);
+            let (condition, then, otherwise) = match decision {
+                Decision::Accept => return out.push_str(&format!("{indent}true\n")),
+                Decision::Reject => return out.push_str(&format!("{indent}false\n")),
+                Decision::Branch(c, t, e) => (c, t.as_ref(), e.as_ref()),
+            };
+
+            // A test between the two leaves is just the condition.
+            let condition = condition.to_rust(schema);
+            match (then, otherwise) {
+                (Decision::Accept, Decision::Reject) => {
+                    return out.push_str(&format!("{indent}{condition}\n"));
                 }
-                '<' => {
-                    new_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        (leftover_ranges.2 .0, rule.threshold - 1),
-                        leftover_ranges.3,
-                    );
-                    leftover_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        (rule.threshold, leftover_ranges.2 .1),
-                        leftover_ranges.3,
-                    );
+                (Decision::Reject, Decision::Accept) => {
+                    return out.push_str(&format!("{indent}!({condition})\n"));
                 }
                 _ => {}
-            },
-            3 => match rule.operation {
-                '>' => {
-                    new_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        (rule.threshold + 1, leftover_ranges.3 .1),
-                    );
-                    leftover_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        (leftover_ranges.3 .0, rule.threshold),
-                    );
-                }
-                '<' => {
-                    new_ranges = (
-                        leftover_ranges.0.clone(),
-                        leftover_ranges.1.clone(),
-                        leftover_ranges.2.clone(),
-                        (leftover_ranges.3 .0, rule.threshold - 1),
-                    );
-                    leftover_ranges = (
-                        leftover_ranges.0,
-                        leftover_ranges.1,
-                        leftover_ranges.2,
-                        (rule.threshold, leftover_ranges.3 .1),
-                    );
+            }
+
+            out.push_str(&format!("{indent}if {condition} {{\n"));
+            visit(then, schema, depth + 1, out);
+
+            // Chain the else branches that are decisions themselves.
+            let mut otherwise = otherwise;
+            while let Decision::Branch(c, t, e) = otherwise {
+                if t.count_branches() + e.count_branches() == 0 {
+                    break;
                 }
-                _ => {}
-            },
-            _ => {}
+                out.push_str(&format!("{indent}}} else if {} {{\n", c.to_rust(schema)));
+                visit(t, schema, depth + 1, out);
+                otherwise = e;
+            }
+
+            out.push_str(&format!("{indent}}} else {{\n"));
+            visit(otherwise, schema, depth + 1, out);
+            out.push_str(&format!("{indent}}}\n"));
         }
 
-        // dbg!(&wf);
-        // dbg!(&new_ranges);
-        // println!("-> continue to {}", rule.target);
-        match rule.target.as_str() {
-            "A" => sum += ranges_to_permutations(new_ranges),
-            "R" => {}
-            val => sum += approved_permutations(workflows, val, new_ranges),
+        let mut out = String::from("// Generated from the day19 workflows.\n\n");
+        out.push_str("pub struct Part {\n");
+        for name in &schema.names {
+            out.push_str(&format!("    pub {name}: u64,\n"));
+        }
+        out.push_str("}\n\n");
+
+        out.push_str("pub fn accept(part: &Part) -> bool {\n");
+        visit(self, schema, 1, &mut out);
+        out.push_str("}\n");
+
+        return out;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+struct Diagnostic {
+    line: usize,
+    column: usize,
+    severity: Severity,
+    message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}:{}: {severity}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Checks the workflows for mistakes that would make the solvers panic or loop forever (undefined
+/// targets and cycles that parts go around), and for rules or workflows that make no difference.
+/// Diagnostics are sorted by their location in the input.
+fn lint(schema: &Schema, workflows: &HashMap<String, Workflow>) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut report = |line: usize, column: usize, severity: Severity, message: String| {
+        diagnostics.push(Diagnostic {
+            line,
+            column,
+            severity,
+            message,
+        })
+    };
+
+    // Visit workflows in input order, so the reports are stable.
+    let mut by_line: Vec<(&str, &Workflow)> = workflows
+        .iter()
+        .map(|(name, wf)| (name.as_str(), wf))
+        .collect();
+    by_line.sort_by_key(|(_, wf)| wf.line);
+
+    if !workflows.contains_key("in") {
+        report(
+            1,
+            1,
+            Severity::Error,
+            "there is no `in` workflow".to_string(),
+        );
+    }
+
+    for (name, wf) in &by_line {
+        for (target, column) in wf.targets() {
+            if target != "A" && target != "R" && !workflows.contains_key(target) {
+                let message = format!("`{name}` sends parts to `{target}`, which is not defined");
+                report(wf.line, column, Severity::Error, message);
+            }
+        }
+    }
+
+    // Anything not reached following the targets from `in` is never used.
+    let mut reached: HashSet<&str> = HashSet::new();
+    let mut pending = vec!["in"];
+    while let Some(name) = pending.pop() {
+        let Some(wf) = workflows.get(name) else {
+            continue;
+        };
+
+        if reached.insert(name) {
+            pending.extend(wf.targets().map(|(target, _)| target));
+        }
+    }
+
+    for (name, wf) in &by_line {
+        if !reached.contains(name) {
+            let message = format!("`{name}` can't be reached from `in`");
+            report(wf.line, 1, Severity::Warning, message);
+        }
+    }
+
+    // Follow the points of the whole domain through each workflow, to find rules that get none of
+    // them, or all of them.
+    for (name, wf) in &by_line {
+        let parts = wf.partition(vec![HyperRect::new(vec![DOMAIN; schema.names.len()])]);
+        for (i, rule) in wf.ruleset.iter().enumerate() {
+            if parts[i].is_empty() {
+                let message = format!(
+                    "rule {} of `{name}` can never fire, no part reaching it passes its condition",
+                    i + 1
+                );
+                report(wf.line, rule.column, Severity::Warning, message);
+            } else if parts[i + 1..].iter().all(|rects| rects.is_empty()) {
+                let message = format!(
+                    "rule {} of `{name}` always fires, the rules after it and the default are never used",
+                    i + 1
+                );
+                report(wf.line, rule.column, Severity::Warning, message);
+                break;
+            }
+        }
+    }
+
+    // Parts that come back to a workflow they went through go around forever, which the
+    // solvers can't deal with. Loops in the graph that no part can follow are harmless.
+    let mut live = vec![];
+    if workflows.contains_key("in") {
+        let region = HyperRect::new(vec![DOMAIN; schema.names.len()]);
+        find_live_cycles(workflows, "in", region, &mut vec![], &mut live);
+    }
+
+    let mut reported: HashSet<Vec<&str>> = HashSet::new();
+    for (path, column) in live {
+        if reported.insert(rotate_cycle(&path)) {
+            let wf = &workflows[path[path.len() - 1]];
+            let message = format!(
+                "parts go around a cycle of workflows forever: {} -> {}",
+                path.join(" -> "),
+                path[0]
+            );
+            report(wf.line, column, Severity::Error, message);
+        }
+    }
+
+    let mut cycles = vec![];
+    let mut done: HashSet<&str> = HashSet::new();
+    for (name, _) in &by_line {
+        if !done.contains(name) {
+            find_cycles(workflows, name, &mut vec![], &mut done, &mut cycles);
+        }
+    }
+
+    for (path, column) in cycles {
+        if reported.insert(rotate_cycle(&path)) {
+            let wf = &workflows[path[path.len() - 1]];
+            let message = format!(
+                "workflows form a cycle, but no part can go around it: {} -> {}",
+                path.join(" -> "),
+                path[0]
+            );
+            report(wf.line, column, Severity::Warning, message);
         }
     }
 
-    // dbg!(&leftover_ranges);
-    // println!("-> continue to {}", wf.default);
-    match wf.default.as_str() {
-        "A" => sum += ranges_to_permutations(leftover_ranges),
-        "R" => {}
-        val => sum += approved_permutations(workflows, val, leftover_ranges),
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    return diagnostics;
+}
+
+/// Follows the points of `region` from `wf_name` like the part 2 solver, recording every path on
+/// which a box of them comes back to a workflow already in `stack`, along with the column of the
+/// rule that closes it. Undefined targets are skipped, they have their own diagnostic.
+fn find_live_cycles<'a>(
+    workflows: &'a HashMap<String, Workflow>,
+    wf_name: &str,
+    region: HyperRect,
+    stack: &mut Vec<&'a str>,
+    cycles: &mut Vec<(Vec<&'a str>, usize)>,
+) {
+    let Some((name, wf)) = workflows.get_key_value(wf_name) else {
+        return;
+    };
+    stack.push(name.as_str());
+
+    for ((target, column), rects) in wf.targets().zip(wf.partition(vec![region])) {
+        for rect in rects {
+            if let Some(pos) = stack.iter().position(|n| *n == target) {
+                cycles.push((stack[pos..].to_vec(), column));
+            } else if target != "A" && target != "R" {
+                find_live_cycles(workflows, target, rect, stack, cycles);
+            }
+        }
     }
 
-    return sum;
+    stack.pop();
 }
 
-fn ranges_to_permutations(ranges: ((u64, u64), (u64, u64), (u64, u64), (u64, u64))) -> u64 {
-    return (1 + ranges.0 .1 - ranges.0 .0)
-        * (1 + ranges.1 .1 - ranges.1 .0)
-        * (1 + ranges.2 .1 - ranges.2 .0)
-        * (1 + ranges.3 .1 - ranges.3 .0);
+/// The workflows of a cycle starting at the least name, so the same cycle found from different
+/// workflows compares equal.
+fn rotate_cycle<'a>(path: &[&'a str]) -> Vec<&'a str> {
+    let first = (0..path.len()).min_by_key(|i| path[*i]).unwrap_or(0);
+    return path[first..]
+        .iter()
+        .chain(&path[..first])
+        .copied()
+        .collect();
 }
 
-fn process_part2(workflows: &HashMap<String, Workflow>) -> u64 {
-    // All possible parts have attributes in the range 1 to 4000.
-    let initial_ranges = ((1, 4000), (1, 4000), (1, 4000), (1, 4000));
+/// Depth first search from `name`, recording every path that comes back to a workflow already in
+/// `stack`, along with the column of the rule that closes it.
+fn find_cycles<'a>(
+    workflows: &'a HashMap<String, Workflow>,
+    name: &'a str,
+    stack: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+    cycles: &mut Vec<(Vec<&'a str>, usize)>,
+) {
+    stack.push(name);
+
+    for (target, column) in workflows[name].targets() {
+        if !workflows.contains_key(target) {
+            continue;
+        }
 
-    return approved_permutations(workflows, "in", initial_ranges);
+        if let Some(pos) = stack.iter().position(|n| *n == target) {
+            cycles.push((stack[pos..].to_vec(), column));
+        } else if !done.contains(target) {
+            find_cycles(workflows, target, stack, done, cycles);
+        }
+    }
+
+    stack.pop();
+    done.insert(name);
 }
 
 fn main() {
-    println!("2023 AoC - Day 19");
+    // The attributes can be declared with `--attributes x,m,a,s`, otherwise they are inferred.
+    // Any other argument selects a command.
+    let mut args = std::env::args().skip(1);
+    let mut declared = None;
+    let mut command = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--attributes" {
+            let list = args.next().expect("Missing the list of attributes!");
+            declared = Some(Schema::declare(&list).unwrap_or_else(|err| exit_with(&err)));
+        } else {
+            command.push(arg);
+        }
+    }
 
     // Input gathering
-    let (workflows, parts) = parse_input();
+    let (schema, workflows, parts) = parse_input(declared).unwrap_or_else(|err| exit_with(&err));
+
+    let diagnostics = lint(&schema, &workflows);
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+
+    if command.first().map(|c| c.as_str()) == Some("lint") {
+        for diagnostic in &diagnostics {
+            println!("{diagnostic}");
+        }
+        println!("{} errors, {} warnings", errors, diagnostics.len() - errors);
+        std::process::exit(if errors > 0 { 1 } else { 0 });
+    }
+
+    // The solvers can't deal with undefined workflows or parts going around forever.
+    if errors > 0 {
+        for diagnostic in diagnostics.iter().filter(|d| d.severity == Severity::Error) {
+            eprintln!("{diagnostic}");
+        }
+        exit_with("the workflows have errors, run the `lint` command for details");
+    }
+
+    match command.iter().map(|c| c.as_str()).collect::<Vec<_>>()[..] {
+        [] => {}
+        ["compile", format] => {
+            let region = vec![HyperRect::new(vec![DOMAIN; schema.names.len()])];
+            let tree = Decision::compile(&workflows, "in", region);
+            eprintln!("Decision tree with {} branches.", tree.count_branches());
+
+            match format {
+                "dot" => print!("{}", tree.to_dot(&schema)),
+                "rust" => print!("{}", tree.to_rust(&schema)),
+                _ => exit_with(&format!("Unknown format `{format}`, use `dot` or `rust`")),
+            }
+            return;
+        }
+        ["explain", part] => {
+            let part = parse_part_values(part)
+                .and_then(|values| Part::new(&schema, values))
+                .unwrap_or_else(|err| exit_with(&err));
+
+            let (steps, end) = route(&workflows, &part);
+            println!("{}", format_route(&schema, &steps, end));
+            return;
+        }
+        ["regions"] => {
+            let regions = accepted_regions(&schema, &workflows);
+            for (rect, steps) in &regions {
+                let ranges: Vec<String> = rect
+                    .ranges
+                    .iter()
+                    .zip(&schema.names)
+                    .map(|((lo, hi), name)| format!("{name} in {lo}..={hi}"))
+                    .collect();
+                let volume = match rect.volume() {
+                    Some(volume) => volume.to_string(),
+                    None => "too large".to_string(),
+                };
+                println!("{{{}}} volume {volume}", ranges.join(", "));
+                println!("    {}", format_route(&schema, steps, "A"));
+            }
+
+            let total = total_volume(regions.iter().map(|(rect, _)| rect))
+                .unwrap_or_else(|| exit_with("the accepted combinations don't fit in 128 bits"));
+            println!("{} regions, {total} accepted combinations", regions.len());
+            return;
+        }
+        _ => exit_with(&format!("Unknown command `{}`", command.join(" "))),
+    }
+
+    println!("2023 AoC - Day 19");
 
     // Part 1 ---------------------------------------------------------------
 
@@ -344,7 +1329,12 @@ fn main() {
 
     // Part 2 ---------------------------------------------------------------
 
-    let part2 = process_part2(&workflows);
+    let part2 = process_part2(&schema, &workflows).unwrap_or_else(|err| exit_with(&err));
 
     println!("PART 2: {part2}");
 }
+
+fn exit_with(err: &str) -> ! {
+    eprintln!("ERROR: {err}");
+    std::process::exit(1);
+}
@@ -1,17 +1,12 @@
-#![allow(dead_code)]
+#![allow(clippy::needless_return)]
 
+use cycle::find_cycle_keyed_within;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     io::{stdin, Read},
 };
 
-#[derive(Debug, Clone)]
-enum ModuleType {
-    None,
-    FlipFlop,
-    Conjuction,
-}
-
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Pulse {
     None,
@@ -19,17 +14,314 @@ enum Pulse {
     Low,
 }
 
+/// Behaviour of a module in the netlist. Each kind of module decides what to emit when it receives
+/// a pulse from one of its inputs. Returning `None` means nothing is sent to the outputs.
+trait Behaviour: std::fmt::Debug {
+    fn receive(&mut self, from: &str, pulse: Pulse) -> Option<Pulse>;
+
+    /// Called once after parsing, with the names of every module wired into this one.
+    fn connect(&mut self, _inputs: &[String]) {}
+
+    /// Appends the internal state to `state`, used to detect repeated configurations. Two
+    /// modules of the same kind that write the same values must behave identically from then on.
+    /// Observers whose data only grows (counters, probes) don't take part.
+    fn write_state(&self, _state: &mut Vec<u64>) {}
+
+    /// What an observer has seen so far, shown after the presses. `None` for modules with nothing
+    /// to tell.
+    fn report(&self) -> Option<String> {
+        None
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour>;
+}
+
+impl Clone for Box<dyn Behaviour> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Forwards every pulse it receives. The button always sends it a Low pulse.
+#[derive(Debug, Clone, Default)]
+struct Broadcaster;
+
+impl Behaviour for Broadcaster {
+    fn receive(&mut self, _from: &str, pulse: Pulse) -> Option<Pulse> {
+        Some(pulse)
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+/// Ignores High pulses, toggles on Low and sends its new state.
+#[derive(Debug, Clone, Default)]
+struct FlipFlop {
+    on: bool,
+}
+
+impl Behaviour for FlipFlop {
+    fn receive(&mut self, _from: &str, pulse: Pulse) -> Option<Pulse> {
+        if pulse != Pulse::Low {
+            return None;
+        }
+
+        self.on = !self.on;
+        return Some(if self.on { Pulse::High } else { Pulse::Low });
+    }
+
+    fn write_state(&self, state: &mut Vec<u64>) {
+        state.push(self.on as u64);
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GateKind {
+    And,
+    Nand,
+    Or,
+    Xor,
+}
+
+/// Remembers the last pulse received from each input (initially Low) and combines them with a
+/// logic function every time one of them changes. The puzzle conjunction is a NAND gate.
+#[derive(Debug, Clone)]
+struct Gate {
+    kind: GateKind,
+    memory: Vec<(String, Pulse)>,
+}
+
+impl Gate {
+    fn new(kind: GateKind) -> Gate {
+        Gate {
+            kind,
+            memory: vec![],
+        }
+    }
+}
+
+impl Behaviour for Gate {
+    fn receive(&mut self, from: &str, pulse: Pulse) -> Option<Pulse> {
+        if let Some(entry) = self.memory.iter_mut().find(|(name, _)| name == from) {
+            entry.1 = pulse;
+        }
+
+        let highs = self
+            .memory
+            .iter()
+            .filter(|(_, p)| *p == Pulse::High)
+            .count();
+        let high = match self.kind {
+            GateKind::And => highs == self.memory.len(),
+            GateKind::Nand => highs != self.memory.len(),
+            GateKind::Or => highs > 0,
+            GateKind::Xor => highs % 2 == 1,
+        };
+
+        return Some(if high { Pulse::High } else { Pulse::Low });
+    }
+
+    fn connect(&mut self, inputs: &[String]) {
+        self.memory = inputs
+            .iter()
+            .map(|name| (name.clone(), Pulse::Low))
+            .collect();
+    }
+
+    fn write_state(&self, state: &mut Vec<u64>) {
+        // One bit per input, packed in as many words as needed.
+        for chunk in self.memory.chunks(64) {
+            state.push(
+                chunk
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, p))| *p == Pulse::High)
+                    .fold(0, |acc, (i, _)| acc | 1 << i),
+            );
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+/// Sends the opposite of every pulse it receives.
+#[derive(Debug, Clone, Default)]
+struct Inverter;
+
+impl Behaviour for Inverter {
+    fn receive(&mut self, _from: &str, pulse: Pulse) -> Option<Pulse> {
+        match pulse {
+            Pulse::High => Some(Pulse::Low),
+            Pulse::Low => Some(Pulse::High),
+            Pulse::None => None,
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+/// Forwards every pulse, keeping a count of the Low and High pulses that went through.
+#[derive(Debug, Clone, Default)]
+struct Counter {
+    low: u64,
+    high: u64,
+}
+
+impl Behaviour for Counter {
+    fn receive(&mut self, _from: &str, pulse: Pulse) -> Option<Pulse> {
+        match pulse {
+            Pulse::Low => self.low += 1,
+            Pulse::High => self.high += 1,
+            Pulse::None => {}
+        }
+        Some(pulse)
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(format!("{} low and {} high pulses", self.low, self.high))
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+/// One-pulse delay line: each received pulse is stored, and the previously stored one (initially
+/// Low) is sent in its place.
+#[derive(Debug, Clone)]
+struct Delay {
+    stored: Pulse,
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Delay { stored: Pulse::Low }
+    }
+}
+
+impl Behaviour for Delay {
+    fn receive(&mut self, _from: &str, pulse: Pulse) -> Option<Pulse> {
+        Some(std::mem::replace(&mut self.stored, pulse))
+    }
+
+    fn write_state(&self, state: &mut Vec<u64>) {
+        state.push((self.stored == Pulse::High) as u64);
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+/// Records every pulse it receives, and who sent it, without sending anything.
+#[derive(Debug, Clone, Default)]
+struct Probe {
+    history: Vec<(String, Pulse)>,
+}
+
+impl Behaviour for Probe {
+    fn receive(&mut self, from: &str, pulse: Pulse) -> Option<Pulse> {
+        self.history.push((from.to_string(), pulse));
+        None
+    }
+
+    fn report(&self) -> Option<String> {
+        // Pulses from each sender, in the order they first sent one.
+        let mut senders: Vec<(&str, u64, u64)> = vec![];
+        for (from, pulse) in &self.history {
+            let index = match senders.iter().position(|(name, _, _)| name == from) {
+                Some(index) => index,
+                None => {
+                    senders.push((from, 0, 0));
+                    senders.len() - 1
+                }
+            };
+            match pulse {
+                Pulse::Low => senders[index].1 += 1,
+                Pulse::High => senders[index].2 += 1,
+                Pulse::None => {}
+            }
+        }
+
+        if senders.is_empty() {
+            return Some("no pulses".to_string());
+        }
+        let senders: Vec<String> = senders
+            .iter()
+            .map(|(name, low, high)| format!("{name} sent {low} low and {high} high"))
+            .collect();
+        let last = match self.history.last() {
+            Some((from, pulse)) => format!(", the last one {pulse:?} from {from}"),
+            None => String::new(),
+        };
+        return Some(format!(
+            "{} pulses{last}: {}",
+            self.history.len(),
+            senders.join(", ")
+        ));
+    }
+
+    fn clone_box(&self) -> Box<dyn Behaviour> {
+        Box::new(self.clone())
+    }
+}
+
+type Constructor = fn() -> Box<dyn Behaviour>;
+
+/// Maps the prefix character of a netlist line to the kind of module it creates. Lines without
+/// a registered prefix create the default module (a broadcaster).
+struct Registry {
+    kinds: HashMap<char, Constructor>,
+    default: Constructor,
+}
+
+impl Registry {
+    fn new() -> Registry {
+        let mut registry = Registry {
+            kinds: HashMap::new(),
+            default: || Box::new(Broadcaster),
+        };
+
+        // Puzzle modules.
+        registry.register('%', || Box::<FlipFlop>::default());
+        registry.register('&', || Box::new(Gate::new(GateKind::Nand)));
+
+        // Extras for logic exercises.
+        registry.register('!', || Box::new(Inverter));
+        registry.register('*', || Box::new(Gate::new(GateKind::And)));
+        registry.register('|', || Box::new(Gate::new(GateKind::Or)));
+        registry.register('^', || Box::new(Gate::new(GateKind::Xor)));
+        registry.register('#', || Box::<Counter>::default());
+        registry.register('~', || Box::<Delay>::default());
+        registry.register('?', || Box::<Probe>::default());
+
+        return registry;
+    }
+
+    fn register(&mut self, prefix: char, constructor: Constructor) {
+        self.kinds.insert(prefix, constructor);
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Module {
     name: String,
-    module_type: ModuleType,
+    behaviour: Box<dyn Behaviour>,
     inputs: Vec<String>,
     outputs: Vec<String>,
-    current_pulse: Pulse,
-    internal_data: u64,
 }
 
-fn parse_input() -> HashMap<String, Module> {
+fn parse_input(registry: &Registry) -> HashMap<String, Module> {
     let mut modules = HashMap::new();
     let mut temp_vector: Vec<Module> = vec![];
 
@@ -39,24 +331,23 @@ fn parse_input() -> HashMap<String, Module> {
         .expect("Could not read stdin!");
 
     // Create each module.
-    for line in buff.split("\n") {
+    for line in buff.split('\n') {
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
 
         // Depending on the start, I can determine the type.
-        let mut name = line.split(" -").nth(0).unwrap();
-        let module_type = match name.chars().nth(0).unwrap() {
-            '%' => ModuleType::FlipFlop,
-            '&' => ModuleType::Conjuction,
-            _ => ModuleType::None,
+        let mut name = line.split(" -").next().unwrap();
+        let prefix = name.chars().next().unwrap();
+        let behaviour = match registry.kinds.get(&prefix) {
+            Some(constructor) => {
+                name = &name[prefix.len_utf8()..];
+                constructor()
+            }
+            None => (registry.default)(),
         };
 
-        if name.contains(['&', '%']) {
-            name = &name[1..];
-        }
-
         let outputs: Vec<String> = line
             .split("> ")
             .nth(1)
@@ -69,161 +360,156 @@ fn parse_input() -> HashMap<String, Module> {
         // Create the new module. Inputs will be poulated next.
         let module = Module {
             name: name.to_string(),
-            module_type,
+            behaviour,
             inputs: vec![],
             outputs,
-            current_pulse: Pulse::Low,
-            internal_data: 0,
         };
 
         temp_vector.push(module);
     }
 
     // For each module generate its inputs.
-    while let Some(mut module) = temp_vector.pop() {
-        module.inputs = temp_vector
+    for i in 0..temp_vector.len() {
+        let name = temp_vector[i].name.clone();
+        let inputs: Vec<String> = temp_vector
             .iter()
-            .filter(|&m| m.outputs.contains(&module.name))
+            .filter(|&m| m.outputs.contains(&name))
             .map(|m| m.name.to_string())
             .collect();
 
+        temp_vector[i].behaviour.connect(&inputs);
+        temp_vector[i].inputs = inputs;
+    }
+
+    for module in temp_vector {
         modules.insert(module.name.to_string(), module);
     }
 
     return modules;
 }
 
-fn process_pulse(
-    module: &mut Module,
-    pulse: Pulse,
-    modules: &HashMap<String, Module>,
-) -> Vec<(String, Pulse)> {
-    let mut next_set: Vec<(String, Pulse)> = vec![];
-    match module.module_type {
-        ModuleType::None => {
-            // Broadcast, sends a low signal to all outputs.
-            for m in &module.outputs {
-                next_set.push((m.to_string(), Pulse::Low));
-                // println!("broadcaster -{:?}-> {m}", Pulse::Low);
-            }
-            module.current_pulse = Pulse::Low;
-        }
-
-        ModuleType::FlipFlop => {
-            // Ignores High pulses, toggles on low and sends singals.
-            match pulse {
-                Pulse::Low => {
-                    module.internal_data ^= 1;
-                    module.current_pulse = if module.internal_data == 1 {
-                        Pulse::High
-                    } else {
-                        Pulse::Low
-                    };
-
-                    for m in &module.outputs {
-                        next_set.push((m.to_string(), module.current_pulse));
-                        // println!("{} -{:?}-> {m}", module.name, module.current_pulse);
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        ModuleType::Conjuction => {
-            // If all inputs are High, sends a Low, else sends High.
-            let inputs: Vec<Pulse> = modules
-                .iter()
-                .filter(|(_, m)| m.outputs.contains(&module.name))
-                .map(|(_, m)| m.current_pulse)
-                .collect();
-
-            module.current_pulse = if inputs.contains(&Pulse::Low) {
-                Pulse::High
-            } else {
-                Pulse::Low
-            };
-
-            for m in &module.outputs {
-                next_set.push((m.to_string(), module.current_pulse));
-                // println!("{} -{:?}-> {m}", module.name, module.current_pulse);
-            }
-        }
+/// Delivers a pulse to a module and returns the pulses it emits, as (from, to, pulse).
+fn process_pulse(module: &mut Module, from: &str, pulse: Pulse) -> Vec<(String, String, Pulse)> {
+    match module.behaviour.receive(from, pulse) {
+        Some(sent) => module
+            .outputs
+            .iter()
+            .map(|m| (module.name.to_string(), m.to_string(), sent))
+            .collect(),
+        None => vec![],
     }
-
-    return next_set;
 }
 
 fn gather_state(modules: &HashMap<String, Module>) -> Vec<u64> {
     // To ensure consistent order, I'll modules by name.
-    let mut temp: Vec<&Module> = modules.iter().map(|(_, m)| m).collect();
+    let mut temp: Vec<&Module> = modules.values().collect();
     temp.sort_by(|m1, m2| m1.name.cmp(&m2.name));
-    let internals = temp.iter().map(|m| m.internal_data).collect();
+
+    let mut internals = vec![];
+    for m in temp {
+        m.behaviour.write_state(&mut internals);
+    }
 
     return internals;
 }
 
-fn process_part1(modules: &HashMap<String, Module>) -> u64 {
+/// Presses the button once, propagating every pulse until the network settles. Returns the
+/// amount of (Low, High) pulses sent.
+fn press_button(modules: &mut HashMap<String, Module>) -> (u64, u64) {
     let mut low_pulses = 0;
     let mut high_pulses = 0;
-    let mut cycles = 0;
-
-    let mut part1_modules = modules.clone();
-    let mut processing: Vec<(String, Pulse)> = vec![];
+    let mut processing: VecDeque<(String, String, Pulse)> = VecDeque::new();
 
-    let mut record: HashMap<Vec<u64>, (u64, u64)> = HashMap::new();
+    processing.push_back(("button".to_string(), "broadcaster".to_string(), Pulse::Low));
 
-    // Get the initial state:
-    let internals = gather_state(&modules);
-    record.insert(internals, (low_pulses, high_pulses));
+    while let Some((from, name, pulse)) = processing.pop_front() {
+        // Increase statistics.
+        match pulse {
+            Pulse::Low => low_pulses += 1,
+            Pulse::High => high_pulses += 1,
+            _ => {}
+        }
 
-    loop {
-        cycles += 1;
-        // println!(">> Cycle {cycles}");
+        // Skip modules that are only named as outputs, like `output` or `rx`.
+        if let Some(module) = modules.get_mut(name.as_str()) {
+            processing.extend(process_pulse(module, &from, pulse));
+        }
+    }
 
-        // println!("button -{:?}-> broadcaster", Pulse::Low);
-        processing.push(("broadcaster".to_string(), Pulse::Low));
+    return (low_pulses, high_pulses);
+}
 
-        while processing.len() != 0 {
-            let (name, pulse) = processing.remove(0);
+/// Counts the (Low, High) pulses sent over `presses` button presses.
+///
+/// Only the compact state of the network is recorded after every press, along with the pulses
+/// the next press sends. Once a state repeats, the presses before its first appearance are the
+/// prefix, and the presses in between are a period that repeats forever, so the totals follow
+/// without pressing any further. If `presses` is reached before any repetition, the simulation
+/// is direct.
+fn count_pulses(modules: &HashMap<String, Module>, presses: u64) -> (u64, u64) {
+    if presses == 0 {
+        return (0, 0);
+    }
 
-            // Increase statistics.
-            match pulse {
-                Pulse::Low => low_pulses += 1,
-                Pulse::High => high_pulses += 1,
-                _ => {}
-            }
+    let press = |mut modules: HashMap<String, Module>| {
+        let sent = press_button(&mut modules);
+        (modules, sent)
+    };
+
+    let limit = usize::try_from(presses).unwrap_or(usize::MAX);
+    match find_cycle_keyed_within(modules.clone(), limit, press, gather_state) {
+        Ok(cycle) => {
+            println!(
+                "Found a cycle of {} presses, starting after press {}.",
+                cycle.period, cycle.prefix
+            );
+            // The pulses of press i are those sent leaving the state after i - 1 presses.
+            return (
+                cycle.sum_through(presses - 1, |sent| sent.0),
+                cycle.sum_through(presses - 1, |sent| sent.1),
+            );
+        }
+        Err(sent) => {
+            return sent
+                .iter()
+                .fold((0, 0), |(low, high), s| (low + s.0, high + s.1));
+        }
+    }
+}
 
-            // Skip processing some named modules that are not defined.
-            if name == "output" || name == "rx" {
-                continue;
-            }
+/// Most presses walked one by one to show what the observers saw.
+const MAX_REPORTED_PRESSES: u64 = 100000;
 
-            // Get a mutable handle of the module, and process the signal.
-            let reference = part1_modules.clone();
-            let module = part1_modules
-                .get_mut(name.as_str())
-                .expect(format!("Should work {name}").as_str());
-            processing.append(&mut process_pulse(module, pulse, &reference));
-        }
+/// Presses the button `presses` times on a copy of the network, at most `MAX_REPORTED_PRESSES`,
+/// and prints the report of every observer module.
+fn print_reports(modules: &HashMap<String, Module>, presses: u64) {
+    if modules.values().all(|m| m.behaviour.report().is_none()) {
+        return;
+    }
 
-        // Evaluate current state of internal status and current outputs to detect cycles.
-        // dbg!(low_pulses, high_pulses);
-        let new_internals = gather_state(&part1_modules);
-        if record.get(&new_internals) != None {
-            println!("Found a cycle, the signals will repeat after {cycles} rounds.");
-            break;
-        } else {
-            record.insert(new_internals, (low_pulses, high_pulses));
-        }
+    let pressed = presses.min(MAX_REPORTED_PRESSES);
+    let mut modules = modules.clone();
+    for _ in 0..pressed {
+        press_button(&mut modules);
+    }
 
-        if cycles == 1000 {
-            println!("Reached 1000 cycles... ");
-            break;
+    println!("Observers after {pressed} presses:");
+    let mut names: Vec<&String> = modules.keys().collect();
+    names.sort();
+    for name in names {
+        if let Some(report) = modules[name].behaviour.report() {
+            println!("  {name}: {report}");
         }
     }
+    println!();
+}
+
+fn process_part1(modules: &HashMap<String, Module>, presses: u64) -> u128 {
+    let (low_pulses, high_pulses) = count_pulses(modules, presses);
 
-    dbg!(low_pulses, high_pulses, 1000 / cycles);
-    return low_pulses * high_pulses * ((1000 / cycles) as u64).pow(2);
+    dbg!(low_pulses, high_pulses);
+    // For large amounts of presses the product no longer fits in 64 bits.
+    return low_pulses as u128 * high_pulses as u128;
 }
 
 fn process_part2(modules: &HashMap<String, Module>) -> u64 {
@@ -253,31 +539,26 @@ fn process_part2(modules: &HashMap<String, Module>) -> u64 {
     let mut input_cycles = vec![0; inputs.len()];
 
     let mut part2_modules = modules.clone();
-    let mut processing: Vec<(String, Pulse)> = vec![];
+    let mut processing: VecDeque<(String, String, Pulse)> = VecDeque::new();
 
     // Find a cycle for each of kh inputs...
     'cycle_loop: loop {
         cycles += 1;
 
-        processing.push(("broadcaster".to_string(), Pulse::Low));
-        while processing.len() != 0 {
-            let (name, pulse) = processing.remove(0);
-
-            // Skip processing some named modules that are not defined.
-            if name == "output" || name == "rx" {
+        processing.push_back(("button".to_string(), "broadcaster".to_string(), Pulse::Low));
+        while let Some((from, name, pulse)) = processing.pop_front() {
+            // Skip modules that are only named as outputs, like `output` or `rx`.
+            let Some(module) = part2_modules.get_mut(name.as_str()) else {
                 continue;
-            }
+            };
 
-            // Get a mutable handle of the module, and process the signal.
-            let reference = part2_modules.clone();
-            let module = part2_modules
-                .get_mut(name.as_str())
-                .expect(format!("Should work {name}").as_str());
-            processing.append(&mut process_pulse(module, pulse, &reference));
+            let sent = process_pulse(module, &from, pulse);
+            let sent_high = sent.first().map(|(_, _, p)| *p) == Some(Pulse::High);
+            processing.extend(sent);
 
-            if inputs.contains(&module.name) && module.current_pulse == Pulse::High {
+            if inputs.contains(&name) && sent_high {
                 println!(">P2 {name} sends High after {cycles} cycles");
-                let idx = inputs.iter().position(|m| m == &module.name).unwrap();
+                let idx = inputs.iter().position(|m| m == &name).unwrap();
                 if input_cycles[idx] != 0 {
                     continue;
                 }
@@ -303,15 +584,24 @@ fn process_part2(modules: &HashMap<String, Module>) -> u64 {
 fn main() {
     println!("2023 AoC - Day 20");
 
+    // The amount of button presses for part 1 can be given as the first argument.
+    let presses = match std::env::args().nth(1) {
+        Some(arg) => arg.parse().expect("Invalid amount of presses!"),
+        None => 1000,
+    };
+
     // Input gathering
-    let modules = parse_input();
+    let registry = Registry::new();
+    let modules = parse_input(&registry);
 
     // Part 1 ---------------------------------------------------------------
 
-    let part1 = process_part1(&modules);
+    let part1 = process_part1(&modules, presses);
 
     println!("PART 1: {part1}\n");
 
+    print_reports(&modules, presses);
+
     // Part 2 ---------------------------------------------------------------
 
     let part2 = process_part2(&modules);
@@ -1,4 +1,6 @@
-#[allow(dead_code)]
+#![allow(clippy::needless_return)]
+
+use cycle::{find_cycle_keyed, Cycle};
 use std::{
     collections::HashMap,
     io::{stdin, Read},
@@ -33,154 +35,421 @@ impl Map {
         return Some((x as i64, y as i64));
     }
 
-    fn can_walk(&self, x: i64, y: i64) -> Option<usize> {
-        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
-            return None;
-        }
-
-        let index = y * self.width as i64 + x;
-        if self.chars[index as usize] == '#' {
-            return None;
+    /// Steps it takes to walk into tile `idx` moving by `(dx, dy)`, or `None` if it can't be
+    /// entered that way. Digits are plots that take that many steps, and arrows are plots that
+    /// can only be entered moving the way they point.
+    fn cost(&self, idx: usize, (dx, dy): (i64, i64)) -> Option<u32> {
+        match self.chars[idx] {
+            '#' => None,
+            '>' => (dx == 1).then_some(1),
+            '<' => (dx == -1).then_some(1),
+            '^' => (dy == -1).then_some(1),
+            'v' => (dy == 1).then_some(1),
+            c => Some(c.to_digit(10).unwrap_or(1)),
         }
+    }
 
-        return Some(index as usize);
+    /// Whether every plot takes one step to enter from any side, as on the puzzle garden.
+    fn is_plain(&self) -> bool {
+        return self.chars.iter().all(|c| matches!(c, '.' | 'S' | '#'));
     }
 
-    fn can_walk_part2(&self, x: i64, y: i64) -> Option<usize> {
-        let x = (self.width as i64 + x) % self.width as i64;
-        let y = (self.height as i64 + y) % self.height as i64;
+    /// Neighbours of tile `idx` on the garden, along with the move that reaches each one.
+    fn neighbours(&self, idx: usize) -> Vec<(usize, (i64, i64))> {
+        let (x, y) = self.to_point(idx).unwrap();
+        return [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .into_iter()
+            .filter_map(|(dx, dy)| Some((self.to_index(x + dx, y + dy)?, (dx, dy))))
+            .collect();
+    }
 
-        let index = y * self.width as i64 + x;
-        if self.chars[index as usize] == '#' {
-            return None;
-        }
+    /// Tiles the elf starts from.
+    fn starts(&self) -> Vec<usize> {
+        return (0..self.chars.len())
+            .filter(|idx| self.chars[*idx] == 'S')
+            .collect();
+    }
 
-        return Some(index as usize);
+    /// Adds or removes a rock, leaving a plain plot if removed.
+    fn set_rock(&mut self, idx: usize, rock: bool) {
+        self.chars[idx] = if rock { '#' } else { '.' };
     }
 
-    fn print(&self) {
+    /// Prints the garden, with `O` on the given plots.
+    fn print(&self, plots: &[(usize, usize)]) {
+        let mut chars = self.chars.clone();
+        for (x, y) in plots {
+            chars[y * self.width + x] = 'O';
+        }
+
         println!("Map {}x{}", self.width, self.height);
-        for i in 0..self.height {
-            let line: String = self.chars[i * self.width..(i + 1) * self.width]
-                .iter()
-                .collect();
-            print!("{}", line);
+        for line in chars.chunks(self.width) {
+            println!("{}", line.iter().collect::<String>());
         }
-        println!("\n");
+        println!();
     }
 }
 
-fn process_part1(map: &Map) -> u64 {
-    // Walk the cells figuring out which are acessible at each iteration.
-    let mut nodes: Vec<usize> = vec![];
-    let mut record: HashMap<usize, Vec<usize>> = HashMap::new();
-    let mut steps = 0;
-
-    // Add the initial spot.
-    nodes.push(
-        map.chars
-            .iter()
-            .position(|c| *c == 'S')
-            .expect("Start not found?"),
-    );
-
-    while steps < 64 {
-        let mut next_nodes: Vec<usize> = vec![];
-        while nodes.len() != 0 {
-            let idx = nodes.pop().unwrap();
-
-            // Check if this cell was already evaulated.
-            match record.get(&idx) {
-                Some(cells) => next_nodes.extend(cells),
-                None => {
-                    let (x, y) = map.to_point(idx).unwrap();
-                    let mut cell_nodes: Vec<usize> = vec![];
-                    for dir in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
-                        if let Some(index) = map.can_walk(x + dir.0, y + dir.1) {
-                            cell_nodes.push(index);
-                        }
-                    }
+/// Steps the elf has to walk on the garden.
+const PART1_STEPS: u64 = 64;
+
+/// Steps the elf has to walk on the infinite garden.
+const PART2_STEPS: u64 = 26501365;
+
+/// How many garden widths of samples the general extrapolation can look at before giving up.
+const MAX_SAMPLES: u64 = 10;
+
+/// Most plots the garden copies of the extrapolation can have, to keep the search in memory.
+const MAX_TILED_CELLS: usize = 1 << 25;
+
+/// Most steps it can take to enter a plot.
+const MAX_COST: u32 = 9;
 
-                    record.insert(idx, cell_nodes.clone());
-                    next_nodes.extend(cell_nodes);
+/// Something to do with the garden after solving the puzzle.
+#[derive(Debug)]
+enum Action {
+    // Count the plots reachable in each amount of steps.
+    Count(Vec<u64>),
+    // Print the plots reachable in an amount of steps.
+    Show(u64),
+    // Add or remove a rock.
+    Rock((i64, i64), bool),
+}
+
+/// Shortest distances from the starts to every plot of a garden, found with a single search. The
+/// garden can be repeated `2 * radius + 1` times in each direction, with the original copy in the
+/// middle, to walk as if it was infinite.
+///
+/// On plain gardens the elf can waste steps walking back and forth, so a plot is reachable in `n`
+/// steps if it is that close through a path with the same parity. Otherwise coming back can cost
+/// more than two steps, or be impossible past one-way plots, so the plots reachable after each
+/// amount of steps are walked one by one instead, until they repeat.
+#[derive(Debug)]
+struct DistanceField {
+    width: usize,
+    // Plots the elf can be on after exactly each amount of steps, as a bitset, when the parity of
+    // the distances isn't enough.
+    layers: Option<Cycle<Vec<u64>>>,
+    // Two per plot, for an even and an odd amount of steps. `u32::MAX` if it can't be reached.
+    distances: Vec<u32>,
+    // Plots at each distance or closer with the same parity: plots reachable in that many steps.
+    reachable: Vec<u64>,
+    // Distances shorter than this are the same as on the infinite garden.
+    trusted: u64,
+}
+
+impl DistanceField {
+    /// Distances on the garden alone.
+    fn new(map: &Map, starts: &[usize]) -> DistanceField {
+        // A start boxed in by rocks can't waste any steps either.
+        let stuck = |start: &usize| {
+            let moves = map.neighbours(*start);
+            !moves.into_iter().any(|(n, d)| map.cost(n, d).is_some())
+        };
+        let layers = match map.is_plain() && !starts.iter().any(stuck) {
+            true => None,
+            false => Some(exact_layers(map, starts)),
+        };
+
+        return DistanceField {
+            trusted: u64::MAX,
+            layers,
+            ..DistanceField::tiled(map, starts, 0)
+        };
+    }
+
+    fn tiled(map: &Map, starts: &[usize], radius: usize) -> DistanceField {
+        let copies = 2 * radius + 1;
+        let width = copies * map.width;
+        let height = copies * map.height;
+
+        // Dial's algorithm: plots to visit, bucketed by distance modulo the costliest step.
+        const BUCKETS: usize = MAX_COST as usize + 1;
+        let mut buckets: Vec<Vec<usize>> = vec![vec![]; BUCKETS];
+        let mut distances = vec![u32::MAX; 2 * width * height];
+        let mut pending = 0;
+        for start in starts {
+            let (x, y) = map.to_point(*start).unwrap();
+            let idx = (radius * map.height + y as usize) * width + radius * map.width + x as usize;
+            if distances[2 * idx] != 0 {
+                distances[2 * idx] = 0;
+                buckets[0].push(2 * idx);
+                pending += 1;
+            }
+        }
+
+        let mut reached: Vec<u64> = vec![];
+        let mut distance = 0;
+        while pending > 0 {
+            reached.push(0);
+            let bucket = std::mem::take(&mut buckets[distance as usize % BUCKETS]);
+            pending -= bucket.len();
+            for state in bucket {
+                // Already reached sooner.
+                if distances[state] != distance {
+                    continue;
+                }
+                reached[distance as usize] += 1;
+
+                let idx = state / 2;
+                let (x, y) = (idx % width, idx / width);
+                let neighbours = [
+                    ((x > 0).then(|| idx - 1), (-1, 0)),
+                    ((x + 1 < width).then_some(idx + 1), (1, 0)),
+                    ((y > 0).then(|| idx - width), (0, -1)),
+                    ((y + 1 < height).then_some(idx + width), (0, 1)),
+                ];
+                for (next, direction) in neighbours {
+                    let Some(next) = next else {
+                        continue;
+                    };
+                    let tile = (next / width % map.height) * map.width + next % width % map.width;
+                    let Some(cost) = map.cost(tile, direction) else {
+                        continue;
+                    };
+
+                    let next_distance = distance + cost;
+                    let next_state = 2 * next + next_distance as usize % 2;
+                    if next_distance < distances[next_state] {
+                        distances[next_state] = next_distance;
+                        buckets[next_distance as usize % BUCKETS].push(next_state);
+                        pending += 1;
+                    }
                 }
             }
+            distance += 1;
+        }
+
+        let mut reachable = reached;
+        for d in 2..reachable.len() {
+            reachable[d] += reachable[d - 2];
         }
 
-        // Remove duplicates.
-        next_nodes.sort();
-        next_nodes.dedup();
+        // Anything closer than the edge of the copies, by a whole garden in case paths go around
+        // rocks past it.
+        let side = map.width.min(map.height) as u64;
+        return DistanceField {
+            width,
+            layers: None,
+            distances,
+            reachable,
+            trusted: radius as u64 * side,
+        };
+    }
+
+    /// Plots reachable in exactly `steps` steps: the ones that close, with the same parity.
+    fn reachable(&self, steps: u64) -> u64 {
+        assert!(
+            steps < self.trusted,
+            "{steps} steps go past the garden copies"
+        );
+        if let Some(layers) = &self.layers {
+            let layer = layers.state_at(steps);
+            return layer.iter().map(|w| w.count_ones() as u64).sum();
+        }
 
-        steps += 1;
-        // println!(
-        //     "After {steps} steps, the elf can reach {} cells.",
-        //     next_nodes.len()
-        // );
+        let last = self.reachable.len() as u64 - 1;
+        if steps <= last {
+            return self.reachable[steps as usize];
+        }
+        if (steps - last).is_multiple_of(2) {
+            return self.reachable[last as usize];
+        }
+        return if last == 0 {
+            0
+        } else {
+            self.reachable[last as usize - 1]
+        };
+    }
 
-        nodes = next_nodes;
+    /// Plots reachable in exactly each amount of steps.
+    fn reachable_each(&self, steps: &[u64]) -> Vec<u64> {
+        return steps.iter().map(|s| self.reachable(*s)).collect();
     }
 
-    // Return the value.
-    return nodes.len() as u64;
+    /// Positions of the plots reachable in exactly `steps` steps.
+    fn plots(&self, steps: u64) -> Vec<(usize, usize)> {
+        if let Some(layers) = &self.layers {
+            let layer = layers.state_at(steps);
+            return (0..64 * layer.len())
+                .filter(|idx| layer[idx / 64] & 1 << (idx % 64) != 0)
+                .map(|idx| (idx % self.width, idx / self.width))
+                .collect();
+        }
+
+        let parity = steps as usize % 2;
+        return (0..self.distances.len() / 2)
+            .filter(|idx| self.distances[2 * idx + parity] as u64 <= steps)
+            .map(|idx| (idx % self.width, idx / self.width))
+            .collect();
+    }
 }
 
-fn process_part2(map: &Map) -> u64 {
-    // Walk the cells figuring out which are acessible at each iteration.
-    let mut nodes: Vec<usize> = vec![];
-    let mut record: HashMap<usize, Vec<usize>> = HashMap::new();
-    let mut steps = 0;
-
-    // Add the initial spot.
-    nodes.push(
-        map.chars
-            .iter()
-            .position(|c| *c == 'S')
-            .expect("Start not found?"),
-    );
-
-    while steps < 26501365 {
-        let mut next_nodes: Vec<usize> = vec![];
-        while nodes.len() != 0 {
-            let idx = nodes.pop().unwrap();
-
-            // Check if this cell was already evaulated.
-            match record.get(&idx) {
-                Some(cells) => next_nodes.extend(cells),
-                None => {
-                    let (x, y) = map.to_point(idx).unwrap();
-                    let mut cell_nodes: Vec<usize> = vec![];
-                    for dir in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
-                        if let Some(index) = map.can_walk_part2(x + dir.0, y + dir.1) {
-                            cell_nodes.push(index);
-                        }
-                    }
+/// Plots the elf can be on after exactly each amount of steps on the garden alone, as bitsets.
+///
+/// A plot taking `c` steps to enter can be reached after `n` steps if a neighbour could be reached
+/// after `n - c`, so each amount of steps follows from the last `MAX_COST` ones. Those eventually
+/// repeat, and so does everything after them.
+fn exact_layers(map: &Map, starts: &[usize]) -> Cycle<Vec<u64>> {
+    let words = map.chars.len().div_ceil(64);
+    let mut first = vec![0; words];
+    for start in starts {
+        first[start / 64] |= 1 << (start % 64);
+    }
 
-                    record.insert(idx, cell_nodes.clone());
-                    next_nodes.extend(cell_nodes);
-                }
+    // Every way into each plot, as (from, cost).
+    let entries: Vec<Vec<(usize, usize)>> = (0..map.chars.len())
+        .map(|idx| {
+            map.neighbours(idx)
+                .into_iter()
+                .filter_map(|(from, (dx, dy))| {
+                    let cost = map.cost(idx, (-dx, -dy))?;
+                    Some((from, cost as usize))
+                })
+                .collect()
+        })
+        .collect();
+
+    // The last `MAX_COST` layers, oldest first, with nothing before the start.
+    let mut window = vec![vec![0; words]; MAX_COST as usize - 1];
+    window.push(first);
+
+    let step = |mut window: Vec<Vec<u64>>| {
+        let mut next = vec![0; words];
+        for (idx, ways) in entries.iter().enumerate() {
+            let reached = ways.iter().any(|(from, cost)| {
+                let layer = &window[window.len() - cost];
+                layer[from / 64] & 1 << (from % 64) != 0
+            });
+            if reached {
+                next[idx / 64] |= 1 << (idx % 64);
             }
         }
 
-        // Remove duplicates.
-        next_nodes.sort();
-        next_nodes.dedup();
+        let current = window.last().unwrap().clone();
+        window.remove(0);
+        window.push(next);
+        (window, current)
+    };
 
-        steps += 1;
-        println!(
-            "After {steps} steps, the elf can reach {} cells.",
-            next_nodes.len()
-        );
+    return find_cycle_keyed(window, step, |window| window.concat());
+}
+
+/// Garden copies needed to walk `steps` steps from the middle one.
+fn copies_radius(map: &Map, steps: u64) -> usize {
+    return (steps / map.width.min(map.height) as u64) as usize + 2;
+}
 
-        nodes = next_nodes;
+/// Plots reachable in exactly `steps` steps on the garden repeated infinitely in every direction.
+///
+/// Once the elf has walked far enough, every extra period of the garden adds a ring of copies
+/// which grows by the same amount each time, so the plots reachable in `r + k * period` steps are
+/// a quadratic function of `k`. It is sampled for a few `k` and extrapolated.
+fn process_part2(map: &Map, starts: &[usize], steps: u64) -> Result<u64, String> {
+    // Only walking back and forth keeps the growth regular.
+    if !map.is_plain() {
+        return Err("the garden has costly or one-way plots".to_string());
     }
 
-    // Return the value.
-    return nodes.len() as u64;
+    // Walking diagonally only lines up with whole copies of the garden every period if it's as
+    // wide as it's high.
+    if map.width != map.height {
+        return Err(format!(
+            "the garden is {}x{}, not square",
+            map.width, map.height
+        ));
+    }
+
+    let (x, y) = map.to_point(starts[0]).unwrap();
+    let period = map.width as u64;
+    let remainder = steps % period;
+
+    // On gardens with a single start in the middle of a clear row and column, the elf reaches a
+    // new ring of copies each period right away, and four samples are enough to check it.
+    // Otherwise it can take a few periods for the growth to settle.
+    let centered = starts.len() == 1 && x as u64 == period / 2 && y as u64 == period / 2;
+    let plain = |x: i64, y: i64| matches!(map.chars[map.to_index(x, y).unwrap()], '.' | 'S');
+    let clear =
+        (0..map.width as i64).all(|x| plain(x, y)) && (0..map.height as i64).all(|y| plain(x, y));
+    let samples = if centered && clear { 4 } else { MAX_SAMPLES };
+
+    // Few enough steps to walk them, on as many copies as they need.
+    let last = remainder + (samples - 1) * period;
+    let radius = copies_radius(map, steps.min(last));
+    let copies = 2 * radius + 1;
+    let cells = copies
+        .checked_mul(copies)
+        .and_then(|c| c.checked_mul(map.width * map.height));
+    if cells.is_none_or(|c| c > MAX_TILED_CELLS) {
+        return Err(format!(
+            "{copies}x{copies} copies of the garden are too many to search"
+        ));
+    }
+
+    let distances = DistanceField::tiled(map, starts, radius);
+    if steps <= last {
+        return Ok(distances.reachable(steps));
+    }
+
+    let f: Vec<i128> = (0..samples)
+        .map(|k| distances.reachable(remainder + k * period) as i128)
+        .collect();
+    let second: Vec<i128> = f.windows(3).map(|w| w[2] - 2 * w[1] + w[0]).collect();
+
+    // The first sample from which the second differences stay the same.
+    let Some(settled) = second.windows(2).position(|w| w[0] == w[1]) else {
+        return Err(format!(
+            "the reachable plots don't grow quadratically within {samples} garden periods"
+        ));
+    };
+
+    let k = (steps / period - settled as u64) as i128;
+    let first = f[settled + 1] - f[settled];
+    let total = f[settled] + k * first + k * (k - 1) / 2 * second[settled];
+
+    return u64::try_from(total).map_err(|_| format!("{total} plots don't fit in 64 bits"));
 }
 
 fn main() {
     println!("2023 AoC - Day 21");
 
+    // Queries on the garden and edits to it, done in order after solving the puzzle.
+    let mut actions = vec![];
+    let mut starts: Vec<(i64, i64)> = vec![];
+    let parse_point = |arg: Option<String>| -> (i64, i64) {
+        let arg = arg.expect("Missing a tile position, like 3,4");
+        let (x, y) = arg
+            .split_once(',')
+            .expect("Tile positions are given as x,y");
+        return (
+            x.trim().parse().expect("Invalid x coordinate"),
+            y.trim().parse().expect("Invalid y coordinate"),
+        );
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--steps" => {
+                let list = args
+                    .next()
+                    .expect("Missing the amounts of steps, like 6,10,64");
+                let steps = list
+                    .split(',')
+                    .map(|s| s.trim().parse().expect("Invalid amount of steps"))
+                    .collect();
+                actions.push(Action::Count(steps));
+            }
+            "--show" => {
+                let s = args.next().expect("Missing the amount of steps");
+                actions.push(Action::Show(s.parse().expect("Invalid amount of steps")));
+            }
+            "--start" => starts.push(parse_point(args.next())),
+            "--rock" => actions.push(Action::Rock(parse_point(args.next()), true)),
+            "--clear" => actions.push(Action::Rock(parse_point(args.next()), false)),
+            other => panic!("Unknown argument {other}"),
+        }
+    }
+
     let mut buff = String::new();
     stdin()
         .read_to_string(&mut buff)
@@ -195,7 +464,19 @@ fn main() {
         chars.extend(line.chars());
     }
 
-    let map = Map {
+    if let Some(idx) = chars
+        .iter()
+        .position(|c| !matches!(c, '.' | '#' | 'S' | '1'..='9' | '<' | '>' | '^' | 'v'))
+    {
+        panic!(
+            "Unknown tile '{}' at {},{}",
+            chars[idx],
+            idx % width,
+            idx / width
+        );
+    }
+
+    let mut map = Map {
         width,
         height,
         chars,
@@ -204,13 +485,83 @@ fn main() {
     // Part 1 ---------------------------------------------------------------
     //
 
-    let part1 = process_part1(&map);
+    let garden_starts = map.starts();
+    assert!(!garden_starts.is_empty(), "Start not found?");
+    let field = DistanceField::new(&map, &garden_starts);
+
+    let part1 = field.reachable(PART1_STEPS);
     println!("PART 1: {part1}");
 
     // Part 2 ---------------------------------------------------------------
     //
 
-    let part2 = process_part2(&map);
-    println!("PART 2: {part2}");
-}
+    match process_part2(&map, &garden_starts, PART2_STEPS) {
+        Ok(part2) => println!("PART 2: {part2}"),
+        Err(err) => println!("PART 2: can't be extrapolated, {err}"),
+    }
+
+    // Queries ----------------------------------------------------------------
+    //
+
+    let to_index = |map: &Map, (x, y): (i64, i64)| {
+        map.to_index(x, y)
+            .unwrap_or_else(|| panic!("Tile {x},{y} is outside the garden"))
+    };
+    let starts: Vec<usize> = if starts.is_empty() {
+        garden_starts
+    } else {
+        starts.into_iter().map(|p| to_index(&map, p)).collect()
+    };
 
+    // Last count for each amount of steps, to see how edits change them.
+    let mut counts: HashMap<u64, u64> = HashMap::new();
+    let mut field = None;
+    for action in actions {
+        match action {
+            Action::Rock(point, rock) => {
+                let idx = to_index(&map, point);
+                assert!(!starts.contains(&idx), "Can't place a rock on a start");
+                map.set_rock(idx, rock);
+                field = None;
+
+                let (x, y) = point;
+                let change = if rock { "added" } else { "removed" };
+                println!("Rock {change} at {x},{y}");
+            }
+            Action::Count(steps) => {
+                let field = field.get_or_insert_with(|| DistanceField::new(&map, &starts));
+
+                // Each walker on its own too, if there are several.
+                let walkers: Vec<DistanceField> = match starts.len() {
+                    1 => vec![],
+                    _ => starts
+                        .iter()
+                        .map(|s| DistanceField::new(&map, &[*s]))
+                        .collect(),
+                };
+
+                for (s, plots) in steps.iter().zip(field.reachable_each(&steps)) {
+                    let change = match counts.insert(*s, plots) {
+                        Some(previous) if previous != plots => {
+                            format!(" ({:+})", plots as i64 - previous as i64)
+                        }
+                        _ => String::new(),
+                    };
+                    let each: Vec<String> = walkers
+                        .iter()
+                        .map(|w| w.reachable(*s).to_string())
+                        .collect();
+                    let each = match each.is_empty() {
+                        true => String::new(),
+                        false => format!(", each walker {}", each.join(" / ")),
+                    };
+                    println!("  in {s} steps: {plots} plots{change}{each}");
+                }
+            }
+            Action::Show(steps) => {
+                let field = field.get_or_insert_with(|| DistanceField::new(&map, &starts));
+                map.print(&field.plots(steps));
+            }
+        }
+    }
+}